@@ -0,0 +1,55 @@
+#![no_main]
+
+//! Parses an arbitrary object file and links it with the default script
+//! up to relocation. Malformed inputs must be rejected with errors.
+
+use libfuzzer_sys::fuzz_target;
+use kld::elf::{context::Context, file::{ElfObjectFile, FileParser}};
+use kld::kos_application::KosApplication;
+use kld::layout::{Layout, SectionMapping};
+use kld::merge::MergedSections;
+use kld::options::IcfMode;
+use kld::{gc, icf, merge, relocation, script, undefined};
+
+fuzz_target!(|data: &[u8]| {
+    let mut context = Context::new();
+    let mut object_file = ElfObjectFile::new("fuzz.o".to_owned(), None);
+
+    if object_file.parse(data, &mut context).is_err() {
+        return;
+    }
+
+    context.objects.push(object_file);
+
+    let script = script::parse(script::DEFAULT_SCRIPT).expect("cannot parse the default script");
+    let mapping = SectionMapping::new(&context, &script);
+    let script_symbols = script.defined_symbols();
+
+    let Ok(gc::LiveSections { sections: mut live_sections, .. }) = gc::collect_live_sections(&context, &mapping, &script_symbols, "_start") else {
+        return;
+    };
+
+    if !undefined::find_undefined_symbols(&context, &mapping, &script_symbols, &live_sections).is_empty() {
+        return;
+    }
+
+    let mut merged_sections = MergedSections::default();
+
+    icf::fold_identical_sections(&context, &mut live_sections, IcfMode::All, &mut merged_sections);
+    merge::merge_sections(&context, &live_sections, &mut merged_sections);
+
+    let Ok(mut layout) = Layout::new(
+        &context,
+        &script,
+        &mapping,
+        &live_sections,
+        merged_sections,
+        KosApplication::HEADER_SIZE
+    ) else {
+        return;
+    };
+
+    layout.emit();
+
+    let _ = relocation::relocate(&context, &mut layout);
+});
@@ -0,0 +1,245 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use object::{Object, ObjectSymbol};
+use crate::elf::{context::{Context, SymbolEntry}, file::{ElfObjectFile, ElfSymbolType, FileParser}, utils};
+use crate::error::ParseError;
+use crate::reader::RawArchiveFile;
+
+/// Archive member extracted for an undefined symbol.
+pub struct Extraction {
+    /// File referencing the symbol, `<entry>` for the entry symbol.
+    pub reference: String,
+    /// `archive(member)`.
+    pub extracted: String,
+    pub symbol: String,
+}
+
+/// Extracts archive members on demand like lld, after the object files
+/// are loaded into the context.
+///
+/// A member is extracted when it defines a symbol that is still undefined
+/// and strongly referenced by a loaded file, or the entry symbol. The
+/// first member defining the symbol in archive order is taken, references
+/// of extracted members extract further members. Returns the extractions
+/// in load order.
+pub fn extract_members(
+    context: &mut Context,
+    archives: &[RawArchiveFile],
+    entry: &str
+) -> Result<Vec<Extraction>, ParseError> {
+    // symbol -> (archive index, member index) of the first definition
+    let mut definitions = HashMap::<String, (usize, usize)>::new();
+
+    for (archive_index, archive) in archives.iter().enumerate() {
+        for (member_index, member) in archive.objects.iter().enumerate() {
+            let object_file = object::File::parse(&*member.data)
+                .map_err(|error| ParseError::Object {
+                    file: format!("{}({})", archive.filename, member.filename),
+                    message: error.to_string(),
+                })?;
+
+            for symbol in object_file.symbols() {
+                let is_global = utils::is_global_symbol(&symbol) || utils::is_weak_symbol(&symbol);
+
+                // tentative definitions don't extract members
+                if !is_global || symbol.is_undefined() || symbol.is_common() {
+                    continue;
+                }
+
+                if let Some(name) = utils::get_symbol_name(&symbol, None) {
+                    definitions.entry(name).or_insert((archive_index, member_index));
+                }
+            }
+        }
+    }
+
+    let mut queue = VecDeque::<(String, String)>::new();
+
+    queue.push_back(("<entry>".to_owned(), entry.to_owned()));
+
+    for object in context.objects.iter() {
+        queue.extend(get_strong_references(object));
+    }
+
+    let mut extracted = HashSet::<(usize, usize)>::new();
+    let mut extractions = Vec::new();
+
+    while let Some((reference, symbol_name)) = queue.pop_front() {
+        if let Some(SymbolEntry::Resolved(_)) = context.symbol_map.get(&symbol_name) {
+            continue;
+        }
+
+        let Some(&(archive_index, member_index)) = definitions.get(&symbol_name) else {
+            continue;
+        };
+
+        if !extracted.insert((archive_index, member_index)) {
+            continue;
+        }
+
+        let archive = &archives[archive_index];
+        let member = &archive.objects[member_index];
+
+        log::debug!("extract {}({}) for {}", archive.filename, member.filename, symbol_name);
+
+        let mut object_file = ElfObjectFile::new(member.filename.to_owned(), Some(archive.filename.to_owned()));
+        object_file.parse(&member.data, context)?;
+
+        queue.extend(get_strong_references(&object_file));
+
+        extractions.push(Extraction {
+            reference,
+            extracted: object_file.get_display_name(),
+            symbol: symbol_name,
+        });
+
+        context.objects.push(object_file);
+    }
+
+    Ok(extractions)
+}
+
+/// Returns (file, symbol) pairs of undefined symbols the object file
+/// references, weak references don't extract members.
+fn get_strong_references(object: &ElfObjectFile) -> Vec<(String, String)> {
+    let file_name = object.get_display_name();
+
+    object.symbols()
+        .iter()
+        .filter_map(|symbol| {
+            let symbol = (**symbol).borrow();
+            let name = symbol.name.as_deref()?;
+
+            let strong_reference = symbol.sym_type == ElfSymbolType::External
+                && !symbol.weak
+                && !name.contains(utils::LOCAL_SYMBOL_SUFFIX);
+
+            strong_reference.then(|| (file_name.to_owned(), name.to_owned()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use object::{write, Architecture, BinaryFormat, Endianness, SectionKind, SymbolFlags, SymbolKind, SymbolScope};
+    use crate::reader::RawObjectFile;
+    use super::*;
+
+    /// Builds an i386 object defining and referencing the symbols,
+    /// weak references start with `?`.
+    fn build_object(definitions: &[&str], references: &[&str]) -> Vec<u8> {
+        let mut object = write::Object::new(BinaryFormat::Elf, Architecture::I386, Endianness::Little);
+        let text = object.add_section(Vec::new(), b".text".to_vec(), SectionKind::Text);
+
+        object.append_section_data(text, &vec![0xC3; definitions.len().max(1)], 1);
+
+        for (index, name) in definitions.iter().enumerate() {
+            object.add_symbol(write::Symbol {
+                name: name.as_bytes().to_vec(),
+                value: index as u64,
+                size: 1,
+                kind: SymbolKind::Text,
+                scope: SymbolScope::Linkage,
+                weak: false,
+                section: write::SymbolSection::Section(text),
+                flags: SymbolFlags::None,
+            });
+        }
+
+        for name in references {
+            let (name, weak) = match name.strip_prefix('?') {
+                Some(name) => (name, true),
+                None => (*name, false),
+            };
+
+            object.add_symbol(write::Symbol {
+                name: name.as_bytes().to_vec(),
+                value: 0,
+                size: 0,
+                kind: SymbolKind::Unknown,
+                scope: SymbolScope::Unknown,
+                weak,
+                section: write::SymbolSection::Undefined,
+                flags: SymbolFlags::None,
+            });
+        }
+
+        object.write().unwrap()
+    }
+
+    /// Loads `main.o` and extracts members of `lib.a`, returns
+    /// (reference, extracted, symbol) triples.
+    fn extract(main: (&[&str], &[&str]), members: &[(&str, &[&str], &[&str])]) -> Vec<(String, String, String)> {
+        let mut context = Context::new();
+        let mut object_file = ElfObjectFile::new("main.o".to_owned(), None);
+
+        object_file.parse(&build_object(main.0, main.1), &mut context).unwrap();
+        context.objects.push(object_file);
+
+        let archive = RawArchiveFile {
+            filename: "lib.a".to_owned(),
+            objects: members
+                .iter()
+                .map(|&(filename, definitions, references)| RawObjectFile {
+                    filename: filename.to_owned(),
+                    data: build_object(definitions, references),
+                })
+                .collect(),
+        };
+
+        let extractions = extract_members(&mut context, &[archive], "_start").unwrap();
+
+        assert!(context.duplicate_symbols.is_empty());
+        assert_eq!(context.objects.len(), extractions.len() + 1);
+
+        extractions
+            .into_iter()
+            .map(|extraction| (extraction.reference, extraction.extracted, extraction.symbol))
+            .collect()
+    }
+
+    fn extraction(reference: &str, extracted: &str, symbol: &str) -> (String, String, String) {
+        (reference.to_owned(), format!("lib.a({})", extracted), symbol.to_owned())
+    }
+
+    #[test]
+    fn members_are_extracted_for_undefined_symbols_only() {
+        let extractions = extract(
+            (&["_start"], &["foo"]),
+            &[
+                ("start.o", &["_start"], &[]),
+                ("foo.o", &["foo"], &["bar"]),
+                ("unused.o", &["unused"], &[]),
+                ("bar.o", &["bar"], &[]),
+            ]
+        );
+
+        assert_eq!(extractions, [
+            extraction("main.o", "foo.o", "foo"),
+            extraction("lib.a(foo.o)", "bar.o", "bar"),
+        ]);
+    }
+
+    #[test]
+    fn entry_symbol_extracts_a_member() {
+        let extractions = extract((&[], &[]), &[("start.o", &["_start"], &["foo"]), ("foo.o", &["foo"], &[])]);
+
+        assert_eq!(extractions, [
+            extraction("<entry>", "start.o", "_start"),
+            extraction("lib.a(start.o)", "foo.o", "foo"),
+        ]);
+    }
+
+    #[test]
+    fn first_member_defining_the_symbol_is_extracted() {
+        let extractions = extract((&["_start"], &["foo"]), &[("a.o", &["foo"], &[]), ("b.o", &["foo"], &[])]);
+
+        assert_eq!(extractions, [extraction("main.o", "a.o", "foo")]);
+    }
+
+    #[test]
+    fn weak_references_do_not_extract_members() {
+        let extractions = extract((&["_start"], &["?foo"]), &[("foo.o", &["foo"], &[])]);
+
+        assert!(extractions.is_empty());
+    }
+}
@@ -1,18 +1,57 @@
-use std::{collections::HashMap, rc::Weak, cell::RefCell};
+use std::{collections::{HashMap, HashSet}, fmt, rc::Weak, cell::RefCell};
 
 use super::file::{ElfSymbol, ElfSymbolType, ElfObjectFile};
+use super::utils;
 
 #[derive(Debug, Clone)]
 pub enum SymbolEntry {
-    Unresolved,
+    /// Referenced without a definition, `weak` while every reference is
+    /// `STB_WEAK`, such references resolve to 0.
+    Unresolved { weak: bool },
     Resolved(Weak<RefCell<ElfSymbol>>)
 }
 
+/// Symbol defined by more than one input file.
+#[derive(Debug)]
+pub struct DuplicateSymbol {
+    pub name: String,
+    /// File of the kept definition.
+    pub first_file: String,
+    pub second_file: String,
+}
+
+impl fmt::Display for DuplicateSymbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let demangled_name = utils::demangle(&self.name);
+
+        if demangled_name == self.name {
+            write!(f, "duplicate symbol: {}", self.name)?;
+        } else {
+            write!(f, "duplicate symbol: {} ({})", demangled_name, self.name)?;
+        }
+
+        write!(f, "\n>>> defined in {}", self.first_file)?;
+        write!(f, "\n>>> defined in {}", self.second_file)
+    }
+}
+
 #[derive(Debug)]
 pub struct Context {
     uid: u64,
     pub symbol_map: HashMap<String, SymbolEntry>,
     pub objects: Vec<ElfObjectFile>,
+    /// Signatures of COMDAT groups kept so far.
+    comdat_groups: HashSet<String>,
+    /// Keep the first definition of duplicate symbols
+    /// (`--allow-multiple-definition`).
+    pub allow_multiple_definition: bool,
+    pub duplicate_symbols: Vec<DuplicateSymbol>,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Context {
@@ -21,42 +60,56 @@ impl Context {
             uid: 0,
             symbol_map: HashMap::new(),
             objects: Vec::new(),
+            comdat_groups: HashSet::new(),
+            allow_multiple_definition: false,
+            duplicate_symbols: Vec::new(),
         }
     }
 
-    pub fn generate_unique_name(&mut self, prefix: &str) -> String {
+    pub fn generate_unique_id(&mut self) -> u64 {
         self.uid += 1;
-        format!("{}{}", prefix, self.uid)
+        self.uid
+    }
+
+    pub fn generate_unique_name(&mut self, prefix: &str) -> String {
+        format!("{}{}", prefix, self.generate_unique_id())
+    }
+
+    /// Registers a COMDAT group signature, returns `false` if a group
+    /// with the same signature is already kept from a previous file.
+    pub fn add_comdat_group(&mut self, signature: &str) -> bool {
+        self.comdat_groups.insert(signature.to_owned())
     }
 
-    pub fn add_local_resolved_symbol(&mut self, symbol: Weak<RefCell<ElfSymbol>>) -> Result<(), ()> {
-        let strong_symbol = symbol.upgrade().ok_or(())?;
+    /// Registers a local symbol defined in a section, returns `false`
+    /// for symbols without a section.
+    pub fn add_local_resolved_symbol(&mut self, symbol: Weak<RefCell<ElfSymbol>>) -> bool {
+        let strong_symbol = symbol.upgrade().expect("cannot get strong symbol");
         let symbol_mut = &mut (*strong_symbol).borrow_mut();
         
         let symbol_name = symbol_mut.name.as_ref()//.take()
             .expect("found local symbol without a name");
 
         if symbol_mut.section.is_none() {
-            return Err(());
+            return false;
         }
 
-        if self.symbol_map.contains_key(symbol_name) {
-            panic!("symbol defined multiple times {}", symbol_name);
-        }
+        match self.symbol_map.get(symbol_name) {
+            Some(SymbolEntry::Resolved(existing_symbol)) => {
+                let existing_symbol = existing_symbol.clone();
+                self.add_duplicate_symbol(symbol_name, &existing_symbol, symbol_mut);
+            }
 
-        self.symbol_map.insert(symbol_name.to_owned(), SymbolEntry::Resolved(symbol));
+            _ => {
+                self.symbol_map.insert(symbol_name.to_owned(), SymbolEntry::Resolved(symbol));
+            }
+        }
 
-        Result::Ok(())
+        true
     }
 
-    pub fn resolve_symbol(&mut self, symbol: Weak<RefCell<ElfSymbol>>) -> Result<(), ()> {
-        let strong_symbol = symbol.upgrade();
-
-        if strong_symbol.is_none() {
-            return Err(());
-        }
-
-        let strong_symbol = strong_symbol.unwrap();
+    pub fn resolve_symbol(&mut self, symbol: Weak<RefCell<ElfSymbol>>) {
+        let strong_symbol = symbol.upgrade().expect("cannot get strong symbol");
         let symbol_mut = &mut (*strong_symbol).borrow_mut();
         
         let symbol_name = symbol_mut.name.as_ref()//.take()
@@ -64,18 +117,78 @@ impl Context {
 
         let symbol_entry = self.symbol_map
             .entry(symbol_name.to_owned())
-            .or_insert(SymbolEntry::Unresolved);
+            .or_insert(SymbolEntry::Unresolved { weak: symbol_mut.weak });
 
-        if symbol_mut.sym_type == ElfSymbolType::Internal {
-            if let SymbolEntry::Resolved(_) = symbol_entry {
-                panic!("symbol defined multiple times");
+        if let SymbolEntry::Unresolved { weak } = symbol_entry {
+            // a strong reference makes the symbol required
+            *weak &= symbol_mut.weak;
+        }
 
-                // TODO: return Err(()); // multiple definition
+        if symbol_mut.sym_type == ElfSymbolType::Internal {
+            if let SymbolEntry::Resolved(existing_symbol) = symbol_entry {
+                let existing_symbol = existing_symbol.clone();
+                let existing_strong_symbol = existing_symbol.upgrade().expect("cannot get strong symbol");
+                let existing_symbol_ref = (*existing_strong_symbol).borrow();
+
+                let existing_weak = existing_symbol_ref.weak;
+                let existing_common = existing_symbol_ref.common;
+                let existing_size = get_common_size(&existing_symbol_ref);
+
+                drop(existing_symbol_ref);
+
+                if existing_common || symbol_mut.common {
+                    let replace = match (existing_common, symbol_mut.common) {
+                        // the largest tentative definition is kept
+                        (true, true) => get_common_size(symbol_mut) > existing_size,
+                        // global definition overrides the tentative one
+                        (true, false) => !symbol_mut.weak,
+                        // tentative definition overrides the weak one
+                        _ => existing_weak,
+                    };
+
+                    if replace {
+                        *symbol_entry = SymbolEntry::Resolved(symbol);
+                    }
+                } else if existing_weak && !symbol_mut.weak {
+                    // global definition overrides the weak one
+                    *symbol_entry = SymbolEntry::Resolved(symbol);
+                } else if !symbol_mut.weak {
+                    let symbol_name = symbol_name.to_owned();
+                    self.add_duplicate_symbol(&symbol_name, &existing_symbol, symbol_mut);
+                }
             } else {
                 *symbol_entry = SymbolEntry::Resolved(symbol);
             }
         }
+    }
+
+    /// Keeps the first definition, records the duplicate unless multiple
+    /// definitions are allowed.
+    fn add_duplicate_symbol(&mut self, name: &str, existing_symbol: &Weak<RefCell<ElfSymbol>>, symbol: &ElfSymbol) {
+        let first_file = existing_symbol
+            .upgrade()
+            .map(|existing_symbol| (*existing_symbol).borrow().get_file_name())
+            .unwrap_or_default();
+
+        let second_file = symbol.get_file_name();
+
+        if self.allow_multiple_definition {
+            log::debug!("keep the first definition of `{}` from {}, skip {}", name, first_file, second_file);
+            return;
+        }
 
-        Ok(())
+        self.duplicate_symbols.push(DuplicateSymbol {
+            name: name.to_owned(),
+            first_file,
+            second_file,
+        });
     }
 }
+
+/// Returns the size of the `COMMON` section of a tentative definition.
+fn get_common_size(symbol: &ElfSymbol) -> usize {
+    symbol.section
+        .as_ref()
+        .and_then(|section| section.upgrade())
+        .map_or(0, |section| (*section).borrow().size)
+}
@@ -1,8 +1,9 @@
 mod object_file;
 pub use object_file::*;
 
+use crate::error::ParseError;
 use super::context::Context;
 
 pub trait FileParser {
-    fn parse(&mut self, data: &[u8], context: &mut Context) -> Result<(), ()>;
+    fn parse(&mut self, data: &[u8], context: &mut Context) -> Result<(), ParseError>;
 }
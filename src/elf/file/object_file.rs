@@ -1,6 +1,8 @@
+use std::collections::HashSet;
 use std::cell::RefCell;
 use std::rc::{Rc, Weak};
-use object::{File, Object, ObjectSymbol, ObjectSection, RelocationTarget, RelocationKind, Symbol, SectionIndex};
+use object::{File, Object, ObjectComdat, ObjectSymbol, ObjectSection, RelocationTarget, RelocationKind, Symbol, SymbolSection, SectionIndex};
+use crate::error::ParseError;
 use super::FileParser;
 use super::super::{context::Context, utils};
 
@@ -33,16 +35,73 @@ pub struct ElfRelocation {
 
 #[derive(Debug)]
 pub struct ElfSection {
+    /// Unique section identifier across all input files.
+    pub uid: u64,
     pub file: Weak<RefCell<ElfObjectFileInner>>,
     pub name: String,
     pub data: Option<Vec<u8>>,
     /// Used for unitialized data.
     pub size: usize,
     pub alignment: usize,
+    /// ELF `sh_flags`.
+    pub flags: u64,
+    /// ELF `sh_entsize`, size of entries in `SHF_MERGE` sections.
+    pub entry_size: usize,
+    /// `SHT_NOBITS` section, e.g. `.bss`.
+    pub uninitialized: bool,
+    /// Member of a COMDAT group already kept from another file.
+    pub discarded: bool,
+    /// Address of the section is taken, it can't be folded by safe ICF.
+    pub address_significant: bool,
+    /// Symbol table, relocations or another section consumed by the linker.
+    pub metadata: bool,
     pub kind: ElfSectionKind,
     pub relocations: Vec<ElfRelocation>,
 }
 
+impl ElfSection {
+    /// Section occupies memory during process execution.
+    pub fn is_alloc(&self) -> bool {
+        self.flags & (object::elf::SHF_ALLOC as u64) != 0
+    }
+
+    /// Returns the name of the input file for diagnostics.
+    pub fn get_file_name(&self) -> String {
+        self.file
+            .upgrade()
+            .map(|file| (*file).borrow().get_display_name())
+            .unwrap_or_default()
+    }
+
+    /// Returns the nearest named symbol of the section at or before
+    /// `offset` with its offset, the source name for local symbols.
+    /// Global symbols are preferred to local ones at the same offset.
+    pub fn get_enclosing_symbol(&self, offset: usize) -> Option<(usize, String)> {
+        let file = self.file.upgrade()?;
+        let file = (*file).borrow();
+
+        file.symbols
+            .iter()
+            .filter_map(|symbol| {
+                let symbol = (**symbol).borrow();
+
+                let symbol_section = symbol.section.as_ref()?.upgrade()?;
+
+                if (*symbol_section).borrow().uid != self.uid || symbol.offset > offset {
+                    return None;
+                }
+
+                let name = symbol.name.as_deref()?;
+                let local = name.contains(utils::LOCAL_SYMBOL_SUFFIX);
+                let name = utils::get_local_symbol_name(name);
+
+                (!name.is_empty()).then(|| (symbol.offset, !local, name.to_owned()))
+            })
+            .max()
+            .map(|(symbol_offset, _, name)| (symbol_offset, name))
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ElfSymbolType {
     /// Links to implementation.
@@ -57,7 +116,27 @@ pub struct ElfSymbol {
     /// Symbol linked section.
     pub section: Option<Weak<RefCell<ElfSection>>>,
     pub offset: usize,
+    /// `st_size`, 0 if the size is unknown.
+    pub size: usize,
     pub sym_type: ElfSymbolType,
+    /// `STB_WEAK` definition is overridden by a global one.
+    pub weak: bool,
+    /// Tentative definition (`SHN_COMMON`), allocated into its own
+    /// `COMMON` section.
+    pub common: bool,
+    /// `SHN_ABS` symbol, the offset is its value.
+    pub absolute: bool,
+    pub file: Weak<RefCell<ElfObjectFileInner>>,
+}
+
+impl ElfSymbol {
+    /// Returns the name of the input file for diagnostics.
+    pub fn get_file_name(&self) -> String {
+        self.file
+            .upgrade()
+            .map(|file| (*file).borrow().get_display_name())
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -69,46 +148,118 @@ pub enum ElfSectionKind {
 #[derive(Debug)]
 pub struct ElfObjectFileInner {
     pub filename: String,
+    /// Archive containing the file.
+    pub archive: Option<String>,
     pub sections: Vec<Rc<RefCell<ElfSection>>>,
     pub symbols: Vec<Rc<RefCell<ElfSymbol>>>,
 }
 
+impl ElfObjectFileInner {
+    /// Returns `archive(member)` for archive members, the filename otherwise.
+    pub fn get_display_name(&self) -> String {
+        match self.archive {
+            Some(ref archive) => format!("{}({})", archive, self.filename),
+            None => self.filename.to_owned(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ElfObjectFile {
     inner: Rc<RefCell<ElfObjectFileInner>>,
 }
 
 impl ElfObjectFile {
-    pub fn new(filename: String) -> Self {
+    pub fn new(filename: String, archive: Option<String>) -> Self {
         Self {
             inner: Rc::new(RefCell::new(ElfObjectFileInner {
                 filename,
+                archive,
                 sections: Vec::new(),
                 symbols: Vec::new(),
             }))
         }
     }
+
+    pub fn sections(&self) -> Vec<Rc<RefCell<ElfSection>>> {
+        self.inner.borrow().sections.clone()
+    }
+
+    pub fn symbols(&self) -> Vec<Rc<RefCell<ElfSymbol>>> {
+        self.inner.borrow().symbols.clone()
+    }
+
+    /// Returns `archive(member)` for archive members, the filename otherwise.
+    pub fn get_display_name(&self) -> String {
+        self.inner.borrow().get_display_name()
+    }
+
+    pub fn is_archive_member(&self) -> bool {
+        self.inner.borrow().archive.is_some()
+    }
+
+    /// Returns the path of the input file, the archive for its members.
+    pub fn get_input_path(&self) -> String {
+        let inner = self.inner.borrow();
+
+        inner.archive
+            .clone()
+            .unwrap_or_else(|| inner.filename.to_owned())
+    }
 }
 
 // private
 
 impl ElfObjectFile {
-    fn parse_sections_without_relocations(&self, object_file: &File) -> Vec<ElfSection> {
+    fn error(&self, message: String) -> ParseError {
+        ParseError::Object {
+            file: self.inner.borrow().get_display_name(),
+            message,
+        }
+    }
+
+    fn parse_sections_without_relocations(
+        &self,
+        object_file: &File,
+        entry_sizes: &[usize],
+        discarded_sections: &HashSet<usize>,
+        context: &mut Context
+    ) -> Result<Vec<ElfSection>, ParseError> {
         let mut sections = Vec::new();
 
         for (index, section) in object_file.sections().enumerate() {
+            let name = match section.name() {
+                Ok(s) => s.to_owned(),
+                Err(_) => format!("section#{}", index),
+            };
+
+            let data = section.data()
+                .map_err(|_| self.error(format!("data of section `{}` is out of bounds", name)))?;
+
+            // sections must fit the 32-bit address space
+            if section.size() > u32::MAX as u64 {
+                return Err(self.error(format!("section `{}` is too large: {} bytes", name, section.size())));
+            }
+
+            let alignment = section.align();
+
+            if alignment > 1 && (!alignment.is_power_of_two() || alignment > u32::MAX as u64) {
+                return Err(self.error(format!("section `{}` has invalid alignment {}", name, alignment)));
+            }
+
             sections.push(ElfSection {
+                uid: context.generate_unique_id(),
                 file: Rc::downgrade(&self.inner),
-                name: match section.name() {
-                    Ok(s) => s.to_owned(),
-                    Err(_) => format!("section#{}", index),
-                },
-                data: match section.data() {
-                    Ok(data) => Some(data.to_owned()),
-                    Err(_) => None,
-                },
+                name,
+                data: Some(data.to_owned()),
                 size: section.size() as usize,
-                alignment: section.align() as usize,
+                alignment: alignment as usize,
+                flags: utils::get_section_flags(&section),
+                entry_size: entry_sizes.get(section.index().0).copied().unwrap_or_default(),
+                uninitialized: utils::is_uninitialized_section(&section),
+                discarded: discarded_sections.contains(&section.index().0),
+                address_significant: false,
+                metadata: utils::is_metadata_section(&section),
                 kind: if utils::is_executable_section(&section) {
                     ElfSectionKind::Code
                 } else {
@@ -118,21 +269,109 @@ impl ElfObjectFile {
             });
         }
 
-        sections
+        Ok(sections)
+    }
+
+    /// Returns indices of sections of COMDAT groups whose signatures are
+    /// already kept from previous files.
+    fn get_discarded_group_sections(object_file: &File, context: &mut Context) -> HashSet<usize> {
+        let mut discarded_sections = HashSet::new();
+
+        for comdat in object_file.comdats() {
+            let Ok(signature) = comdat.name() else {
+                continue;
+            };
+
+            if context.add_comdat_group(signature) {
+                continue;
+            }
+
+            log::debug!("discard COMDAT group `{}`", signature);
+
+            discarded_sections.extend(comdat.sections().map(|index| index.0));
+        }
+
+        discarded_sections
+    }
+
+    /// Marks sections defining symbols listed in `.llvm_addrsig`. Without
+    /// the table every section of the file is address significant.
+    fn parse_address_significance(
+        object_file: &File,
+        symbols: &[Rc<RefCell<ElfSymbol>>],
+        sections: &[Rc<RefCell<ElfSection>>]
+    ) {
+        let Some(address_significance_table) = object_file.section_by_name(".llvm_addrsig") else {
+            for section in sections {
+                (**section).borrow_mut().address_significant = true;
+            }
+
+            return;
+        };
+
+        let data = address_significance_table.data().unwrap_or_default();
+        let mut position = 0;
+
+        while let Some(symbol_index) = utils::read_uleb128(data, &mut position) {
+            let section = symbols
+                .get(symbol_index as usize)
+                .and_then(|symbol| (**symbol).borrow().section.clone())
+                .and_then(|section| section.upgrade());
+
+            if let Some(section) = section {
+                (*section).borrow_mut().address_significant = true;
+            }
+        }
     }
 
     fn parse_sections_relocations(
+        &self,
         object_file: &File,
         symbols: &mut [Rc<RefCell<ElfSymbol>>],
         sections: &mut [Rc<RefCell<ElfSection>>]
-    ) {
+    ) -> Result<(), ParseError> {
         for (index, section) in object_file.sections().enumerate() {
+            // relocations of discarded sections are dropped with them
+            if (*sections[index]).borrow().discarded {
+                continue;
+            }
+
             for (relocation_offset, relocation_data) in section.relocations() {
+                let section_name = (*sections[index]).borrow().name.to_owned();
+
+                let target = Self::get_target_symbol(relocation_data.target(), symbols, sections)
+                    .ok_or_else(|| self.error(format!(
+                        "relocation at `{}`+0x{:X} has invalid target {:?}",
+                        section_name,
+                        relocation_offset,
+                        relocation_data.target()
+                    )))?;
+
+                let kind = Self::get_relocation_kind(relocation_data.kind())
+                    .ok_or_else(|| self.error(format!(
+                        "relocation at `{}`+0x{:X} has unsupported type {:?}",
+                        section_name,
+                        relocation_offset,
+                        relocation_data.kind()
+                    )))?;
+
+                // relocations patch section contents, NOBITS sections have none
+                let size = (relocation_data.size() / 8) as u64;
+                let data_size = (*sections[index]).borrow().data.as_ref().map_or(0, |data| data.len()) as u64;
+
+                if relocation_offset.checked_add(size).is_none_or(|end| end > data_size) {
+                    return Err(self.error(format!(
+                        "relocation at `{}`+0x{:X} is out of section bounds",
+                        section_name,
+                        relocation_offset
+                    )));
+                }
+
                 let relocation = ElfRelocation {
-                    target: Self::get_target_symbol(relocation_data.target(), symbols, sections),
-                    size: (relocation_data.size() / 8) as usize,
+                    target,
+                    size: size as usize,
                     offset: relocation_offset as usize,
-                    kind: Self::get_relocation_kind(relocation_data.kind()),
+                    kind,
                 };
 
                 let mut parsed_section = sections
@@ -143,34 +382,75 @@ impl ElfObjectFile {
                 parsed_section.relocations.push(relocation);
             }
         }
+
+        Ok(())
     }
 
     fn parse_symbols(
+        &self,
         context: &mut Context,
         object_file: &File,
-        sections: &[Rc<RefCell<ElfSection>>]
-    ) -> ParsedSymbols {
+        sections: &mut Vec<Rc<RefCell<ElfSection>>>
+    ) -> Result<ParsedSymbols, ParseError> {
         let mut all_symbols = Vec::new();
         let mut local_symbols = Vec::new();
         let mut global_symbols = Vec::new();
 
         for symbol in object_file.symbols() {
-            let parent_section = Self::get_parent_section(symbol.section_index(), sections);
+            let mut parent_section = match symbol.section_index() {
+                Some(index) => Some(Self::get_parent_section(index, sections).ok_or_else(|| {
+                    self.error(format!("symbol #{} has invalid section index {}", symbol.index().0, index.0))
+                })?),
+
+                None => None,
+            };
+
+            let is_global = utils::is_global_symbol(&symbol) || utils::is_weak_symbol(&symbol);
+
+            // global symbols of a discarded group refer to the kept copy
+            let is_discarded = is_global && parent_section
+                .as_ref()
+                .and_then(|section| section.upgrade())
+                .is_some_and(|section| (*section).borrow().discarded);
+
+            if is_discarded {
+                parent_section = None;
+            }
+
+            if symbol.is_common() && !is_discarded {
+                let section = self.allocate_common_symbol(&symbol, context)?;
+
+                parent_section = Some(Rc::downgrade(&section));
+                sections.push(section);
+            }
 
             // TODO replace multiple global/local methods to a single universal method
 
+            let name = Self::get_symbol_special_name(&symbol, context);
+
+            // symbols are resolved and referenced by their names
+            if name.is_none() {
+                return Err(self.error(format!("symbol #{} has no name", symbol.index().0)));
+            }
+
             let elf_symbol = Rc::new(RefCell::new(ElfSymbol {
-                name: Self::get_symbol_special_name(&symbol, context),
+                name,
                 section: parent_section,
-                offset: symbol.address() as usize,
-                sym_type: if utils::is_external_symbol(&symbol) {
+                // the value of a common symbol is its alignment
+                offset: if symbol.is_common() { 0 } else { symbol.address() as usize },
+                size: symbol.size() as usize,
+                sym_type: if is_discarded || utils::is_external_symbol(&symbol) {
                     ElfSymbolType::External
                 } else {
                     ElfSymbolType::Internal
-                }
+                },
+                weak: utils::is_weak_symbol(&symbol),
+                common: symbol.is_common(),
+                absolute: symbol.section() == SymbolSection::Absolute,
+                file: Rc::downgrade(&self.inner),
             }));
 
-            if utils::is_global_symbol(&symbol) || utils::is_weak_symbol(&symbol) {
+            if is_global {
                 global_symbols.push(Rc::clone(&elf_symbol));
             } else if utils::is_local_symbol(&symbol) && !utils::is_external_symbol(&symbol) {
                 local_symbols.push(Rc::clone(&elf_symbol));
@@ -179,56 +459,85 @@ impl ElfObjectFile {
             all_symbols.push(elf_symbol);
         }
 
-        ParsedSymbols {
-            all: all_symbols,
-            local: local_symbols,
-            global: global_symbols,
+        Ok(
+            ParsedSymbols {
+                all: all_symbols,
+                local: local_symbols,
+                global: global_symbols,
+            }
+        )
+    }
+
+    /// Creates a `SHT_NOBITS` section named `COMMON` for a tentative
+    /// definition, the default script places it into `.bss`.
+    fn allocate_common_symbol(&self, symbol: &Symbol, context: &mut Context) -> Result<Rc<RefCell<ElfSection>>, ParseError> {
+        let alignment = symbol.address();
+
+        if alignment > 1 && (!alignment.is_power_of_two() || alignment > u32::MAX as u64) {
+            return Err(self.error(format!("common symbol #{} has invalid alignment {}", symbol.index().0, alignment)));
         }
+
+        if symbol.size() > u32::MAX as u64 {
+            return Err(self.error(format!("common symbol #{} is too large: {} bytes", symbol.index().0, symbol.size())));
+        }
+
+        Ok(Rc::new(RefCell::new(ElfSection {
+            uid: context.generate_unique_id(),
+            file: Rc::downgrade(&self.inner),
+            name: "COMMON".to_owned(),
+            data: Some(Vec::new()),
+            size: symbol.size() as usize,
+            alignment: alignment as usize,
+            flags: (object::elf::SHF_ALLOC | object::elf::SHF_WRITE) as u64,
+            entry_size: 0,
+            uninitialized: true,
+            discarded: false,
+            address_significant: true,
+            metadata: false,
+            kind: ElfSectionKind::Data,
+            relocations: Vec::new(),
+        })))
     }
 
     fn get_parent_section(
-        section_index: Option<SectionIndex>,
+        section_index: SectionIndex,
         sections: &[Rc<RefCell<ElfSection>>]
     ) -> Option<Weak<RefCell<ElfSection>>> {
-        if let Some(index) = section_index {
-            if let Some(section_cell) = sections.get(index.0) {
-                return Some(Rc::downgrade(section_cell));
-            }
-        }
-
-        None
+        sections
+            .get(section_index.0)
+            .map(Rc::downgrade)
     }
 
     fn get_target_symbol(
         target: RelocationTarget,
         symbols: &[Rc<RefCell<ElfSymbol>>],
         sections: &[Rc<RefCell<ElfSection>>]
-    ) -> ElfRelocationTarget {
+    ) -> Option<ElfRelocationTarget> {
         match target {
             RelocationTarget::Symbol(symbol_index) => {
-                ElfRelocationTarget::Symbol(Rc::downgrade(symbols
+                symbols
                     .get(symbol_index.0)
-                    .expect("cannot get target symbol")))
+                    .map(|symbol| ElfRelocationTarget::Symbol(Rc::downgrade(symbol)))
             }
 
             RelocationTarget::Section(section_index) => {
-                ElfRelocationTarget::Section(Rc::downgrade(sections
+                sections
                     .get(section_index.0)
-                    .expect("cannot get target symbol")))
+                    .map(|section| ElfRelocationTarget::Section(Rc::downgrade(section)))
             }
 
             RelocationTarget::Absolute => {
-                ElfRelocationTarget::Absolute
+                Some(ElfRelocationTarget::Absolute)
             }
 
-            _ => unimplemented!()
+            _ => None
         }
     }
 
-    fn get_relocation_kind(relocation_kind: RelocationKind) -> ElfRelocationKind {
+    fn get_relocation_kind(relocation_kind: RelocationKind) -> Option<ElfRelocationKind> {
         match relocation_kind {
-            RelocationKind::Absolute => ElfRelocationKind::Absolute,
-            RelocationKind::Relative => ElfRelocationKind::Relative,
+            RelocationKind::Absolute => Some(ElfRelocationKind::Absolute),
+            RelocationKind::Relative => Some(ElfRelocationKind::Relative),
             //RelocationKind::Got => todo!(),
             //RelocationKind::GotRelative => todo!(),
             //RelocationKind::GotBaseRelative => todo!(),
@@ -240,13 +549,13 @@ impl ElfObjectFile {
             //RelocationKind::Elf(_) => todo!(),
             //RelocationKind::MachO { value, relative } => todo!(),
             //RelocationKind::Coff(_) => todo!(),
-            _ => unimplemented!()
+            _ => None
         }
     }
 
     fn get_symbol_special_name(symbol: &Symbol, context: &mut Context) -> Option<String> {
         if utils::is_local_symbol(symbol) {
-            let prefix = context.generate_unique_name("#unknown.local.symbol.");
+            let prefix = context.generate_unique_name(utils::LOCAL_SYMBOL_SUFFIX);
 
             if let Some(symbol_name) = utils::get_symbol_name(symbol, None) {
                 Some(format!("{}{}", symbol_name, prefix))
@@ -260,36 +569,54 @@ impl ElfObjectFile {
 }
 
 impl FileParser for ElfObjectFile {
-    fn parse(&mut self, buffer: &[u8], context: &mut Context) -> Result<(), ()> {
-        let object_file = object::File::parse(buffer).map_err(|_| ())?;
-        let sections = self.parse_sections_without_relocations(&object_file);
+    fn parse(&mut self, buffer: &[u8], context: &mut Context) -> Result<(), ParseError> {
+        utils::check_compatibility(buffer)
+            .map_err(|message| ParseError::Incompatible {
+                file: self.inner.borrow().get_display_name(),
+                message,
+            })?;
+
+        let object_file = object::File::parse(buffer)
+            .map_err(|error| self.error(error.to_string()))?;
+        let entry_sizes = utils::get_section_entry_sizes(buffer);
+        let discarded_sections = Self::get_discarded_group_sections(&object_file, context);
+
+        let sections = self.parse_sections_without_relocations(
+            &object_file,
+            &entry_sizes,
+            &discarded_sections,
+            context
+        )?;
         
         let mut sections: Vec<_> = sections
             .into_iter()
             .map(|section| Rc::new(RefCell::new(section)))
             .collect();
 
-        let ParsedSymbols { all: mut all_symbols, local: local_symbols, global: global_symbols} = Self::parse_symbols(
+        let ParsedSymbols { all: mut all_symbols, local: local_symbols, global: global_symbols} = self.parse_symbols(
             context,
             &object_file,
-            &sections
-        );
+            &mut sections
+        )?;
 
-        Self::parse_sections_relocations(&object_file, &mut all_symbols, &mut sections);
+        self.parse_sections_relocations(&object_file, &mut all_symbols, &mut sections)?;
+        Self::parse_address_significance(&object_file, &all_symbols, &sections);
         
         // register sections and symbols
 
-        let mut inner = self.inner.borrow_mut();
+        {
+            let mut inner = self.inner.borrow_mut();
 
-        inner.sections = sections;
-        inner.symbols = all_symbols.clone();
+            inner.sections = sections;
+            inner.symbols = all_symbols.clone();
+        }
 
         for ref local_symbol in local_symbols {
-            let _ = context.add_local_resolved_symbol(Rc::downgrade(local_symbol));
+            context.add_local_resolved_symbol(Rc::downgrade(local_symbol));
         }
 
         for ref global_symbol in global_symbols {
-            context.resolve_symbol(Rc::downgrade(global_symbol))?;
+            context.resolve_symbol(Rc::downgrade(global_symbol));
         }
 
         Result::Ok(())
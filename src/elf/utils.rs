@@ -1,38 +1,182 @@
-use object::{elf, Symbol, ObjectSymbol, Section, ObjectSection, SymbolFlags, SectionFlags, SymbolSection, File, Object};
+use object::read::elf::{FileHeader, SectionHeader};
+use object::{elf, Endianness, FileKind, Symbol, ObjectSymbol, Section, ObjectSection, SymbolFlags, SectionFlags, SectionKind, SymbolSection, File, Object};
 
-pub fn is_local_symbol(symbol: &Symbol) -> bool {
-    if let SymbolFlags::Elf { st_info, .. } = symbol.flags() {
-        (st_info >> 4) == object::elf::STB_LOCAL
-    } else {
-        unimplemented!()
+/// Suffix making names of local symbols unique across input files.
+pub const LOCAL_SYMBOL_SUFFIX: &str = "#unknown.local.symbol.";
+
+/// Returns the source name of a local symbol without the unique suffix.
+pub fn get_local_symbol_name(name: &str) -> &str {
+    name.split_once(LOCAL_SYMBOL_SUFFIX)
+        .map_or(name, |(name, _)| name)
+}
+
+/// Demangles a Rust (or C++) symbol name, returns the name itself on failure.
+pub fn demangle(name: &str) -> String {
+    use symbolic_common::{Language, Name, NameMangling};
+    use symbolic_demangle::{Demangle, DemangleOptions};
+
+    let name = Name::new(name, NameMangling::Mangled, Language::Rust);
+
+    Demangle::try_demangle(&name, DemangleOptions::complete()).into_owned()
+}
+
+/// Returns ELF `st_info`, the parser accepts ELF files only.
+fn get_symbol_info(symbol: &Symbol) -> Option<u8> {
+    match symbol.flags() {
+        SymbolFlags::Elf { st_info, .. } => Some(st_info),
+        _ => None,
     }
 }
 
+pub fn is_local_symbol(symbol: &Symbol) -> bool {
+    get_symbol_info(symbol).is_some_and(|st_info| (st_info >> 4) == object::elf::STB_LOCAL)
+}
+
 pub fn is_global_symbol(symbol: &Symbol) -> bool {
-    if let SymbolFlags::Elf { st_info, .. } = symbol.flags() {
-        (st_info >> 4) == object::elf::STB_GLOBAL
-    } else {
-        unimplemented!()
-    }
+    get_symbol_info(symbol).is_some_and(|st_info| (st_info >> 4) == object::elf::STB_GLOBAL)
 }
 
 pub fn is_weak_symbol(symbol: &Symbol) -> bool {
-    if let SymbolFlags::Elf { st_info, .. } = symbol.flags() {
-        (st_info >> 4) == object::elf::STB_WEAK
-    } else {
-        unimplemented!()
-    }
+    get_symbol_info(symbol).is_some_and(|st_info| (st_info >> 4) == object::elf::STB_WEAK)
 }
 
 pub fn is_executable_section(section: &Section) -> bool {
-    if let SectionFlags::Elf { sh_flags } = section.flags() {
-        sh_flags & (elf::SHF_EXECINSTR as u64) != 0
-    } else {
-        unimplemented!()
+    get_section_flags(section) & (elf::SHF_EXECINSTR as u64) != 0
+}
+
+pub fn is_uninitialized_section(section: &Section) -> bool {
+    matches!(section.kind(), SectionKind::UninitializedData | SectionKind::UninitializedTls)
+}
+
+/// LLVM address significance table, `.llvm_addrsig`.
+const SHT_LLVM_ADDRSIG: u32 = 0x6FFF_4C03;
+
+/// Checks that the section is consumed by the linker (symbol and string
+/// tables, relocations, groups) and isn't an input for the output.
+pub fn is_metadata_section(section: &Section) -> bool {
+    matches!(section.kind(), SectionKind::Metadata | SectionKind::Elf(SHT_LLVM_ADDRSIG))
+}
+
+/// Returns ELF `sh_flags`, the parser accepts ELF files only.
+pub fn get_section_flags(section: &Section) -> u64 {
+    match section.flags() {
+        SectionFlags::Elf { sh_flags } => sh_flags,
+        _ => 0,
+    }
+}
+
+/// Checks that the file can be linked into a KolibriOS executable: a
+/// 32-bit little-endian i386 relocatable file for the System V or GNU ABI.
+pub fn check_compatibility(buffer: &[u8]) -> Result<(), String> {
+    match FileKind::parse(buffer) {
+        Ok(FileKind::Elf32) => {}
+        Ok(FileKind::Elf64) => return Err("64-bit ELF class, expected 32-bit (ELFCLASS32)".to_owned()),
+        _ => return Err("not an ELF file".to_owned()),
+    }
+
+    let header = elf::FileHeader32::<Endianness>::parse(buffer)
+        .map_err(|error| error.to_string())?;
+
+    let ident = header.e_ident();
+
+    if ident.data != elf::ELFDATA2LSB {
+        return Err("big-endian data, expected little-endian (ELFDATA2LSB)".to_owned());
+    }
+
+    if !matches!(ident.os_abi, elf::ELFOSABI_SYSV | elf::ELFOSABI_GNU) || ident.abi_version != 0 {
+        return Err(format!("unsupported OS ABI {} version {}, expected System V or GNU", ident.os_abi, ident.abi_version));
+    }
+
+    let endian = Endianness::Little;
+
+    let file_type = header.e_type(endian);
+
+    if file_type != elf::ET_REL {
+        let file_type = match file_type {
+            elf::ET_EXEC => "executable (ET_EXEC)".to_owned(),
+            elf::ET_DYN => "shared object (ET_DYN)".to_owned(),
+            elf::ET_CORE => "core file (ET_CORE)".to_owned(),
+            _ => format!("type {}", file_type),
+        };
+
+        return Err(format!("unsupported {}, expected relocatable object (ET_REL)", file_type));
+    }
+
+    let machine = header.e_machine(endian);
+
+    if machine != elf::EM_386 {
+        let machine = match machine {
+            elf::EM_X86_64 => "x86-64 (EM_X86_64)".to_owned(),
+            elf::EM_ARM => "ARM (EM_ARM)".to_owned(),
+            elf::EM_AARCH64 => "AArch64 (EM_AARCH64)".to_owned(),
+            elf::EM_RISCV => "RISC-V (EM_RISCV)".to_owned(),
+            _ => format!("{}", machine),
+        };
+
+        return Err(format!("unsupported machine {}, expected i386 (EM_386)", machine));
+    }
+
+    // i386 defines no processor-specific flags
+    let flags = header.e_flags(endian);
+
+    if flags != 0 {
+        return Err(format!("unsupported flags 0x{:X}, expected none for i386", flags));
+    }
+
+    Ok(())
+}
+
+/// Returns `sh_entsize` of every section by its index, the generic
+/// `object` API doesn't expose it.
+pub fn get_section_entry_sizes(buffer: &[u8]) -> Vec<usize> {
+    fn get_entry_sizes<Elf: FileHeader>(buffer: &[u8]) -> Vec<usize> {
+        let Ok(header) = Elf::parse(buffer) else {
+            return Vec::new();
+        };
+
+        let Ok(endian) = header.endian() else {
+            return Vec::new();
+        };
+
+        header.section_headers(endian, buffer)
+            .map(|section_headers| {
+                section_headers
+                    .iter()
+                    .map(|section_header| section_header.sh_entsize(endian).into() as usize)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    match FileKind::parse(buffer) {
+        Ok(FileKind::Elf32) => get_entry_sizes::<elf::FileHeader32<Endianness>>(buffer),
+        Ok(FileKind::Elf64) => get_entry_sizes::<elf::FileHeader64<Endianness>>(buffer),
+        _ => Vec::new(),
     }
 }
 
-pub fn get_symbol_name<'a>(symbol: &'a Symbol, object_file: Option<&File>) -> Option<String> {
+/// Reads an unsigned LEB128 number, returns `None` at the end of data.
+pub fn read_uleb128(data: &[u8], position: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+
+    loop {
+        let byte = *data.get(*position)?;
+        *position += 1;
+
+        if shift < 64 {
+            value |= ((byte & 0x7F) as u64) << shift;
+        }
+
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+
+        shift += 7;
+    }
+}
+
+pub fn get_symbol_name(symbol: &Symbol, object_file: Option<&File>) -> Option<String> {
     if let Ok(symbol_name) = symbol.name() {
         if !symbol_name.is_empty() {
             return Some(symbol_name.to_owned());
@@ -54,19 +198,134 @@ pub fn get_symbol_name<'a>(symbol: &'a Symbol, object_file: Option<&File>) -> Op
     None
 }
 
-/// External symbol is a symbol that has a name and special values:
-/// 
-/// * st_info.type == STT_NOTYPE
-/// 
-/// * shndx == UNDEF
+/// External symbol is a symbol that has a name and is undefined
+/// (shndx == UNDEF) whatever its type.
 pub fn is_external_symbol(symbol: &Symbol) -> bool {
-    let has_no_type = if let SymbolFlags::Elf { st_info, .. } = symbol.flags() {
-        (st_info & 0x0F) == object::elf::STT_NOTYPE
+    let has_name = get_symbol_name(symbol, None).is_some();
+
+    symbol.is_undefined() && has_name
+}
+
+/// Checks that the name can be used as a C identifier:
+///
+/// * consists of `[A-Za-z0-9_]` characters
+///
+/// * doesn't start with a digit
+pub fn is_c_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+
+        _ => false,
+    }
+}
+
+/// Returns the section name encapsulated by the `__start_SECNAME` or
+/// `__stop_SECNAME` symbol name.
+pub fn get_encapsulated_section_name(symbol_name: &str) -> Option<&str> {
+    let section_name = symbol_name
+        .strip_prefix("__start_")
+        .or_else(|| symbol_name.strip_prefix("__stop_"))?;
+
+    if is_c_identifier(section_name) {
+        Some(section_name)
     } else {
-        unimplemented!()
-    };
+        None
+    }
+}
 
-    let has_name = get_symbol_name(symbol, None).is_some();
+#[cfg(test)]
+mod tests {
+    use object::{write, Architecture, BinaryFormat};
+    use super::*;
+
+    /// Offsets in the ELF32 header.
+    const EI_OSABI: usize = 7;
+    const E_TYPE: usize = 16;
+    const E_FLAGS: usize = 36;
+
+    fn build_object(architecture: Architecture, endian: Endianness) -> Vec<u8> {
+        let mut object = write::Object::new(BinaryFormat::Elf, architecture, endian);
+        let text = object.add_section(Vec::new(), b".text".to_vec(), SectionKind::Text);
+
+        object.append_section_data(text, &[0xC3], 1);
+        object.write().unwrap()
+    }
+
+    fn build_i386_object() -> Vec<u8> {
+        build_object(Architecture::I386, Endianness::Little)
+    }
+
+    #[test]
+    fn i386_relocatable_objects_are_compatible() {
+        assert_eq!(check_compatibility(&build_i386_object()), Ok(()));
+
+        let mut data = build_i386_object();
+        data[EI_OSABI] = elf::ELFOSABI_GNU;
+
+        assert_eq!(check_compatibility(&data), Ok(()));
+    }
+
+    #[test]
+    fn non_elf_files_are_rejected() {
+        assert_eq!(check_compatibility(b"!<arch>\n"), Err("not an ELF file".to_owned()));
+    }
+
+    #[test]
+    fn elf64_files_are_rejected() {
+        let data = build_object(Architecture::X86_64, Endianness::Little);
+
+        assert_eq!(check_compatibility(&data), Err("64-bit ELF class, expected 32-bit (ELFCLASS32)".to_owned()));
+    }
+
+    #[test]
+    fn big_endian_files_are_rejected() {
+        let data = build_object(Architecture::I386, Endianness::Big);
 
-    has_no_type && symbol.is_undefined() && has_name
+        assert_eq!(check_compatibility(&data), Err("big-endian data, expected little-endian (ELFDATA2LSB)".to_owned()));
+    }
+
+    #[test]
+    fn shared_objects_are_rejected() {
+        let mut data = build_i386_object();
+        data[E_TYPE..E_TYPE + 2].copy_from_slice(&elf::ET_DYN.to_le_bytes());
+
+        assert_eq!(
+            check_compatibility(&data),
+            Err("unsupported shared object (ET_DYN), expected relocatable object (ET_REL)".to_owned())
+        );
+    }
+
+    #[test]
+    fn x86_64_machine_is_rejected() {
+        // x32 objects are ELF32 for EM_X86_64
+        let data = build_object(Architecture::X86_64_X32, Endianness::Little);
+
+        assert_eq!(
+            check_compatibility(&data),
+            Err("unsupported machine x86-64 (EM_X86_64), expected i386 (EM_386)".to_owned())
+        );
+    }
+
+    #[test]
+    fn processor_flags_are_rejected() {
+        let mut data = build_i386_object();
+        data[E_FLAGS..E_FLAGS + 4].copy_from_slice(&0x10u32.to_le_bytes());
+
+        assert_eq!(check_compatibility(&data), Err("unsupported flags 0x10, expected none for i386".to_owned()));
+    }
+
+    #[test]
+    fn foreign_os_abi_is_rejected() {
+        let mut data = build_i386_object();
+        data[EI_OSABI] = elf::ELFOSABI_FREEBSD;
+
+        assert_eq!(
+            check_compatibility(&data),
+            Err("unsupported OS ABI 9 version 0, expected System V or GNU".to_owned())
+        );
+    }
 }
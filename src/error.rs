@@ -0,0 +1,274 @@
+use std::fmt;
+use crate::elf::context::DuplicateSymbol;
+use crate::script;
+use crate::undefined::UndefinedSymbol;
+
+/// Failure of a link stage.
+#[derive(Debug)]
+pub enum Error {
+    Options(String),
+    Read(ReadError),
+    Parse(ParseError),
+    Resolve(ResolveError),
+    Layout(LayoutError),
+    Relocate(RelocateError),
+    Write(WriteError),
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    Archive {
+        path: String,
+        message: String,
+    },
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    /// Input doesn't match the target.
+    Incompatible {
+        file: String,
+        message: String,
+    },
+    Object {
+        file: String,
+        message: String,
+    },
+    Script {
+        path: String,
+        source: script::ParseError,
+    },
+    /// JSON link report read by `size-diff`.
+    Report {
+        path: String,
+        message: String,
+    },
+    /// Archive written by `--reproduce`.
+    Reproducer {
+        path: String,
+        message: String,
+    },
+}
+
+#[derive(Debug)]
+pub enum ResolveError {
+    EntryNotFound(String),
+    UndefinedSymbols(Vec<UndefinedSymbol>),
+    DuplicateSymbols(Vec<DuplicateSymbol>),
+    DiscardedSectionReferenced {
+        section: String,
+        file: String,
+    },
+}
+
+#[derive(Debug)]
+pub enum LayoutError {
+    UndefinedSymbol(String),
+    UndefinedOutputSection(String),
+    UnresolvedEntry(String),
+    DivisionByZero,
+    AssertionFailed(String),
+    LocationCounterBackwards {
+        from: usize,
+        to: usize,
+    },
+    OverlappingSections {
+        section: String,
+        address: usize,
+        previous_end: usize,
+    },
+    /// Address or end of a section, the location counter or a symbol
+    /// doesn't fit the 32-bit image.
+    AddressOverflow {
+        what: String,
+        address: u64,
+    },
+}
+
+#[derive(Debug)]
+pub enum RelocateError {
+    /// Target symbol is undefined or its section is not placed.
+    UnresolvedTarget {
+        target: String,
+        file: String,
+        section: String,
+    },
+    UnsupportedSize {
+        size: usize,
+        file: String,
+        section: String,
+        offset: usize,
+    },
+    OutOfBounds {
+        file: String,
+        section: String,
+        offset: usize,
+    },
+}
+
+#[derive(Debug)]
+pub struct WriteError {
+    pub path: String,
+    pub source: std::io::Error,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Options(message) => write!(f, "invalid options: {}", message),
+            Self::Read(error) => write!(f, "read failed: {}", error),
+            Self::Parse(error) => write!(f, "parse failed: {}", error),
+            Self::Resolve(error) => write!(f, "symbol resolution failed: {}", error),
+            Self::Layout(error) => write!(f, "layout failed: {}", error),
+            Self::Relocate(error) => write!(f, "relocation failed: {}", error),
+            Self::Write(error) => write!(f, "write failed: {}", error),
+        }
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, source } => write!(f, "cannot read `{}`: {}", path, source),
+            Self::Archive { path, message } => write!(f, "invalid archive `{}`: {}", path, message),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Incompatible { file, message } => write!(f, "{}: incompatible input: {}", file, message),
+            Self::Object { file, message } => write!(f, "{}: {}", file, message),
+            Self::Script { path, source } => write!(f, "linker script {}: {}", path, source),
+            Self::Report { path, message } => write!(f, "link report {}: {}", path, message),
+            Self::Reproducer { path, message } => write!(f, "reproducer {}: {}", path, message),
+        }
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EntryNotFound(entry) => write!(f, "entry point `{}` not found", entry),
+
+            Self::UndefinedSymbols(undefined_symbols) => {
+                write!(f, "{} undefined symbol(s):", undefined_symbols.len())?;
+
+                for undefined_symbol in undefined_symbols {
+                    write!(f, "\n{}", undefined_symbol)?;
+                }
+
+                Ok(())
+            }
+
+            Self::DuplicateSymbols(duplicate_symbols) => {
+                write!(f, "{} duplicate symbol(s):", duplicate_symbols.len())?;
+
+                for duplicate_symbol in duplicate_symbols {
+                    write!(f, "\n{}", duplicate_symbol)?;
+                }
+
+                write!(f, "\nuse --allow-multiple-definition to keep the first definitions")
+            }
+
+            Self::DiscardedSectionReferenced { section, file } => {
+                write!(f, "section `{}` from `{}` is discarded but referenced", section, file)
+            }
+        }
+    }
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedSymbol(name) => write!(f, "undefined symbol `{}` referenced in linker script", name),
+            Self::UndefinedOutputSection(name) => write!(f, "undefined output section `{}` referenced in linker script", name),
+            Self::UnresolvedEntry(name) => write!(f, "cannot resolve the address of the entry point `{}`", name),
+            Self::DivisionByZero => write!(f, "division by zero in linker script"),
+            Self::AssertionFailed(message) => write!(f, "linker script assertion failed: {}", message),
+
+            Self::LocationCounterBackwards { from, to } => {
+                write!(f, "cannot move location counter backwards (from {:08X} to {:08X})", from, to)
+            }
+
+            Self::OverlappingSections { section, address, previous_end } => write!(
+                f,
+                "output section `{}` at {:08X} overlaps the header or a previous section (ends at {:08X})",
+                section,
+                address,
+                previous_end
+            ),
+
+            Self::AddressOverflow { what, address } => {
+                write!(f, "{} exceeds the 32-bit address space ({:X})", what, address)
+            }
+        }
+    }
+}
+
+impl fmt::Display for RelocateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnresolvedTarget { target, file, section } => {
+                write!(f, "cannot resolve the address of `{}` referenced by {}:({})", target, file, section)
+            }
+
+            Self::UnsupportedSize { size, file, section, offset } => {
+                write!(f, "unsupported {}-byte relocation at {}:({}+0x{:X})", size, file, section, offset)
+            }
+
+            Self::OutOfBounds { file, section, offset } => {
+                write!(f, "relocation at {}:({}+0x{:X}) is out of section bounds", file, section, offset)
+            }
+        }
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot write `{}`: {}", self.path, self.source)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ReadError> for Error {
+    fn from(error: ReadError) -> Self {
+        Self::Read(error)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(error: ParseError) -> Self {
+        Self::Parse(error)
+    }
+}
+
+impl From<ResolveError> for Error {
+    fn from(error: ResolveError) -> Self {
+        Self::Resolve(error)
+    }
+}
+
+impl From<LayoutError> for Error {
+    fn from(error: LayoutError) -> Self {
+        Self::Layout(error)
+    }
+}
+
+impl From<RelocateError> for Error {
+    fn from(error: RelocateError) -> Self {
+        Self::Relocate(error)
+    }
+}
+
+impl From<WriteError> for Error {
+    fn from(error: WriteError) -> Self {
+        Self::Write(error)
+    }
+}
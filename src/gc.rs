@@ -0,0 +1,364 @@
+use std::{cell::RefCell, collections::{HashMap, HashSet, VecDeque}, fmt::Write, rc::Rc};
+use crate::elf::{context::{Context, SymbolEntry}, file::{ElfSection, ElfRelocationTarget}, utils};
+use crate::error::{Error, ResolveError};
+use crate::layout::SectionMapping;
+use crate::merge::MergedSections;
+
+/// Why a section is live.
+#[derive(Debug, Clone)]
+pub enum LiveReason {
+    /// Defines the entry symbol.
+    Entry(String),
+    /// Matched by a `KEEP` pattern of the script.
+    Kept,
+    /// Referenced by a relocation at `offset` of the live section with
+    /// the uid, through the symbol if the target is a symbol.
+    Referenced {
+        section: u64,
+        offset: usize,
+        symbol: Option<String>,
+    },
+}
+
+pub struct LiveSections {
+    /// Live sections in BFS order.
+    pub sections: Vec<Rc<RefCell<ElfSection>>>,
+    /// Section uid -> the first reason the section was reached by.
+    pub reasons: HashMap<u64, LiveReason>,
+}
+
+struct Reachability<'a> {
+    context: &'a Context,
+    mapping: &'a SectionMapping,
+    /// Symbols assigned by the linker script.
+    script_symbols: &'a HashSet<&'a str>,
+}
+
+/// Collects all sections reachable from the `root` symbol and `KEEP`
+/// sections through relocations.
+///
+/// Sections are returned in BFS order. A reference to an undefined
+/// `__start_SECNAME`/`__stop_SECNAME` symbol keeps alive every section
+/// of the `SECNAME` output section.
+pub fn collect_live_sections(
+    context: &Context,
+    mapping: &SectionMapping,
+    script_symbols: &HashSet<&str>,
+    root: &str
+) -> Result<LiveSections, ResolveError> {
+    let reachability = Reachability {
+        context,
+        mapping,
+        script_symbols,
+    };
+
+    let mut live_sections = Vec::new();
+    let mut reasons = HashMap::<u64, LiveReason>::new();
+    let mut queue = VecDeque::<(Rc<RefCell<ElfSection>>, LiveReason)>::new();
+
+    queue.extend(
+        reachability
+            .get_symbol_sections(root)
+            .into_iter()
+            .map(|section| (section, LiveReason::Entry(root.to_owned())))
+    );
+
+    queue.extend(mapping.kept.iter().map(|section| (Rc::clone(section), LiveReason::Kept)));
+
+    while let Some((section, reason)) = queue.pop_front() {
+        let section_ref = (*section).borrow();
+
+        if reasons.contains_key(&section_ref.uid) {
+            continue;
+        }
+
+        reasons.insert(section_ref.uid, reason);
+
+        if mapping.discarded.contains(&section_ref.uid) {
+            return Err(ResolveError::DiscardedSectionReferenced {
+                section: section_ref.name.to_owned(),
+                file: section_ref.get_file_name(),
+            });
+        }
+
+        for relocation in section_ref.relocations.iter() {
+            match relocation.target {
+                ElfRelocationTarget::Symbol(ref weak_symbol) => {
+                    let symbol = weak_symbol.upgrade().expect("cannot get strong symbol");
+                    let symbol = (*symbol).borrow();
+                    let symbol_name = symbol.name.as_ref().expect("cannot get symbol name");
+
+                    let reason = LiveReason::Referenced {
+                        section: section_ref.uid,
+                        offset: relocation.offset,
+                        symbol: Some(symbol_name.to_owned()),
+                    };
+
+                    queue.extend(
+                        reachability
+                            .get_symbol_sections(symbol_name)
+                            .into_iter()
+                            .map(|section| (section, reason.clone()))
+                    );
+                }
+
+                ElfRelocationTarget::Section(ref weak_section) => {
+                    let reason = LiveReason::Referenced {
+                        section: section_ref.uid,
+                        offset: relocation.offset,
+                        symbol: None,
+                    };
+
+                    queue.push_back((weak_section.upgrade().expect("got empty weak section"), reason));
+                }
+
+                ElfRelocationTarget::Absolute => {}
+            }
+        }
+
+        drop(section_ref);
+
+        live_sections.push(section);
+    }
+
+    Ok(
+        LiveSections {
+            sections: live_sections,
+            reasons,
+        }
+    )
+}
+
+impl<'a> Reachability<'a> {
+    fn get_symbol_sections(&self, symbol_name: &str) -> Vec<Rc<RefCell<ElfSection>>> {
+        // undefined symbols are reported after the collection
+        let Some(symbol_entry) = self.context.symbol_map.get(symbol_name) else {
+            return Vec::new();
+        };
+
+        match symbol_entry {
+            SymbolEntry::Resolved(weak_symbol) => {
+                let symbol = weak_symbol.upgrade().expect("cannot get symbol");
+                let symbol = (*symbol).borrow();
+
+                // absolute symbols have no section
+                symbol.section
+                    .as_ref()
+                    .map(|section| section.upgrade().expect("got empty weak section"))
+                    .into_iter()
+                    .collect()
+            }
+
+            SymbolEntry::Unresolved { .. } => {
+                if self.script_symbols.contains(symbol_name) {
+                    return Vec::new();
+                }
+
+                let output_section_inputs = utils::get_encapsulated_section_name(symbol_name)
+                    .and_then(|section_name| self.mapping.get_output_section_inputs(section_name));
+
+                output_section_inputs
+                    .map(|sections| sections.to_vec())
+                    .unwrap_or_default()
+            }
+        }
+    }
+}
+
+/// Formats input sections removed by the collection like GNU ld
+/// `--print-gc-sections`, with their sizes. COMDAT duplicates, sections
+/// without `SHF_ALLOC` and sections discarded by the script are not
+/// listed. Live sections folded by ICF are listed separately with the
+/// sections they are folded into.
+pub fn generate_removed_sections_report(
+    context: &Context,
+    mapping: &SectionMapping,
+    reasons: &HashMap<u64, LiveReason>,
+    merged_sections: &MergedSections
+) -> String {
+    let sections = context.objects
+        .iter()
+        .flat_map(|object| object.sections())
+        .collect::<Vec<_>>();
+
+    let mut text = String::new();
+
+    for section in sections.iter() {
+        let section = (**section).borrow();
+
+        let removed = !reasons.contains_key(&section.uid)
+            && section.is_alloc()
+            && !section.metadata
+            && !section.discarded
+            && !mapping.discarded.contains(&section.uid);
+
+        if removed {
+            writeln!(
+                &mut text,
+                "removing unused section '{}' ({} bytes) in file '{}'",
+                section.name,
+                section.size,
+                section.get_file_name()
+            ).expect("cannot append string");
+        }
+    }
+
+    for section in sections.iter() {
+        let section = (**section).borrow();
+
+        let Some(target) = merged_sections.get_folded_target(section.uid) else {
+            continue;
+        };
+
+        let Some(target) = sections.iter().find(|target_section| (***target_section).borrow().uid == target) else {
+            continue;
+        };
+
+        let target = (**target).borrow();
+
+        writeln!(
+            &mut text,
+            "folding identical section '{}' ({} bytes) in file '{}' into '{}' in file '{}'",
+            section.name,
+            section.size,
+            section.get_file_name(),
+            target.name,
+            target.get_file_name()
+        ).expect("cannot append string");
+    }
+
+    text
+}
+
+/// Formats the shortest reference chain from the entry symbol or a `KEEP`
+/// section to every defined symbol matching `pattern`. The pattern is an
+/// exact mangled or demangled name, otherwise a regular expression.
+pub fn explain_live_symbols(
+    context: &Context,
+    reasons: &HashMap<u64, LiveReason>,
+    pattern: &str
+) -> Result<String, Error> {
+    let mut sections = HashMap::<u64, Rc<RefCell<ElfSection>>>::new();
+
+    for object in context.objects.iter() {
+        for section in object.sections() {
+            let uid = (*section).borrow().uid;
+            sections.insert(uid, section);
+        }
+    }
+
+    // (symbol map key, source name, demangled name)
+    let mut symbols = Vec::<(&str, &str, String)>::new();
+
+    for (symbol_name, symbol_entry) in context.symbol_map.iter() {
+        let name = utils::get_local_symbol_name(symbol_name);
+
+        if matches!(symbol_entry, SymbolEntry::Resolved(_)) && !name.is_empty() {
+            symbols.push((symbol_name, name, utils::demangle(name)));
+        }
+    }
+
+    symbols.sort_unstable();
+
+    let mut matched_symbols = symbols
+        .iter()
+        .filter(|(_, name, demangled)| *name == pattern || demangled == pattern)
+        .collect::<Vec<_>>();
+
+    if matched_symbols.is_empty() {
+        let regex = regex::Regex::new(pattern)
+            .map_err(|error| Error::Options(format!("invalid --why-live pattern `{}`: {}", pattern, error)))?;
+
+        matched_symbols = symbols
+            .iter()
+            .filter(|(_, name, demangled)| regex.is_match(name) || regex.is_match(demangled))
+            .collect();
+    }
+
+    let mut text = String::new();
+
+    if matched_symbols.is_empty() {
+        writeln!(&mut text, "{}: no defined symbol matches", pattern).expect("cannot append string");
+    }
+
+    for (symbol_name, name, demangled) in matched_symbols {
+        if name == demangled {
+            writeln!(&mut text, "{}", name).expect("cannot append string");
+        } else {
+            writeln!(&mut text, "{} ({})", demangled, name).expect("cannot append string");
+        }
+
+        let Some(SymbolEntry::Resolved(weak_symbol)) = context.symbol_map.get(*symbol_name) else {
+            continue;
+        };
+
+        let symbol = weak_symbol.upgrade().expect("cannot get symbol");
+        let symbol = (*symbol).borrow();
+
+        let Some(section) = symbol.section.as_ref().and_then(|section| section.upgrade()) else {
+            writeln!(&mut text, "  absolute symbol, not in a section").expect("cannot append string");
+            continue;
+        };
+
+        let section = (*section).borrow();
+
+        writeln!(&mut text, "  defined in {}:{}", section.get_file_name(), section.name).expect("cannot append string");
+
+        let Some(mut reason) = reasons.get(&section.uid) else {
+            writeln!(&mut text, "  not live, the section is discarded").expect("cannot append string");
+            continue;
+        };
+
+        // reasons are recorded in BFS order, so the chain is the shortest
+        loop {
+            match reason {
+                LiveReason::Entry(root) => {
+                    writeln!(&mut text, "  reached from the entry point {}", utils::demangle(root)).expect("cannot append string");
+                    break;
+                }
+
+                LiveReason::Kept => {
+                    writeln!(&mut text, "  kept by KEEP of the linker script").expect("cannot append string");
+                    break;
+                }
+
+                LiveReason::Referenced { section: uid, offset, symbol } => {
+                    let section = (*sections[uid]).borrow();
+
+                    write!(
+                        &mut text,
+                        "  referenced by {} in {}:{}+0x{:x}",
+                        get_site_name(&section, *offset),
+                        section.get_file_name(),
+                        section.name,
+                        offset
+                    ).expect("cannot append string");
+
+                    // section symbols have no names
+                    let symbol = symbol
+                        .as_deref()
+                        .map(utils::get_local_symbol_name)
+                        .filter(|symbol| !symbol.is_empty());
+
+                    match symbol {
+                        Some(symbol) => writeln!(&mut text, " via {}", utils::demangle(symbol)),
+                        None => writeln!(&mut text),
+                    }.expect("cannot append string");
+
+                    reason = &reasons[uid];
+                }
+            }
+        }
+    }
+
+    Ok(text)
+}
+
+/// Returns `symbol+0xOFFSET` for the enclosing symbol of the section,
+/// the section name if there is none.
+fn get_site_name(section: &ElfSection, offset: usize) -> String {
+    match section.get_enclosing_symbol(offset) {
+        Some((symbol_offset, name)) => format!("{}+0x{:x}", utils::demangle(&name), offset - symbol_offset),
+        None => format!("{}+0x{:x}", section.name, offset),
+    }
+}
@@ -0,0 +1,141 @@
+use std::{collections::BTreeMap, fmt::Write};
+use crate::elf::{context::{Context, SymbolEntry}, file::{ElfRelocationTarget, ElfSectionKind}, utils};
+use crate::layout::Layout;
+
+/// Formats the reference graph of placed input sections in Graphviz DOT.
+///
+/// Nodes are named by the first symbol of the section, code sections are
+/// boxes and data sections are ellipses, the font grows with the section
+/// size. Edges are relocations, weighted by their count. Folded sections
+/// are represented by the kept section.
+pub fn generate_reference_graph(context: &Context, layout: &Layout) -> String {
+    let mut graph = String::new();
+
+    writeln!(&mut graph, "digraph kld {{").expect("cannot append string");
+    writeln!(&mut graph, "    node [style=filled];").expect("cannot append string");
+
+    let mut section_symbols = layout.get_section_symbols(context);
+
+    for symbols in section_symbols.values_mut() {
+        // global names first at the same address
+        symbols.sort_unstable_by_key(|(address, symbol_name)| (*address, symbol_name.contains(utils::LOCAL_SYMBOL_SUFFIX)));
+    }
+
+    // nodes
+
+    let mut edges = BTreeMap::<(u64, u64), usize>::new();
+
+    for output_section in layout.output_sections.iter() {
+        for section in output_section.input_sections.iter() {
+            let section = (*section).borrow();
+
+            let name = section_symbols
+                .get(&section.uid)
+                .and_then(|symbols| {
+                    symbols
+                        .iter()
+                        .map(|(_, symbol_name)| utils::get_local_symbol_name(symbol_name))
+                        .find(|symbol_name| !symbol_name.is_empty())
+                })
+                .map_or_else(|| section.name.to_owned(), utils::demangle);
+
+            let (shape, color) = match section.kind {
+                ElfSectionKind::Code => ("box", "lightblue"),
+                ElfSectionKind::Data => ("ellipse", "lightyellow"),
+            };
+
+            let font_size = 10 + 2 * (usize::BITS - section.size.leading_zeros()).min(16);
+
+            writeln!(
+                &mut graph,
+                "    s{} [label=\"{}\\n{} bytes\", tooltip=\"{}:{}\", shape={}, fillcolor={}, fontsize={}];",
+                section.uid,
+                escape(&name),
+                section.size,
+                escape(&section.get_file_name()),
+                escape(&section.name),
+                shape,
+                color,
+                font_size
+            ).expect("cannot append string");
+
+            for relocation in section.relocations.iter() {
+                let targets = match relocation.target {
+                    ElfRelocationTarget::Symbol(ref weak_symbol) => {
+                        let symbol = weak_symbol.upgrade().expect("cannot get strong symbol");
+                        let symbol = (*symbol).borrow();
+                        let symbol_name = symbol.name.as_ref().expect("cannot get symbol name");
+
+                        get_symbol_sections(layout, context, symbol_name)
+                    }
+
+                    ElfRelocationTarget::Section(ref weak_section) => {
+                        let target = weak_section.upgrade().expect("got empty weak section");
+                        let uid = (*target).borrow().uid;
+                        vec![uid]
+                    }
+
+                    ElfRelocationTarget::Absolute => continue,
+                };
+
+                for target in targets {
+                    let target = layout.merged_sections.get_folded_target(target).unwrap_or(target);
+
+                    if layout.section_addresses.contains_key(&target) {
+                        *edges.entry((section.uid, target)).or_default() += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    // edges
+
+    for ((source, target), count) in edges {
+        if count > 1 {
+            writeln!(&mut graph, "    s{} -> s{} [weight={}, label=\"{}\"];", source, target, count, count).expect("cannot append string");
+        } else {
+            writeln!(&mut graph, "    s{} -> s{};", source, target).expect("cannot append string");
+        }
+    }
+
+    writeln!(&mut graph, "}}").expect("cannot append string");
+
+    graph
+}
+
+/// Returns uids of the sections referenced through the symbol: the
+/// section of the definition, or every input of the `SECNAME` output
+/// section for `__start_SECNAME`/`__stop_SECNAME`.
+fn get_symbol_sections(layout: &Layout, context: &Context, symbol_name: &str) -> Vec<u64> {
+    // the definition may override the referenced symbol
+    match context.symbol_map.get(symbol_name) {
+        Some(SymbolEntry::Resolved(weak_symbol)) => {
+            let symbol = weak_symbol.upgrade().expect("cannot get symbol");
+            let symbol = (*symbol).borrow();
+
+            symbol.section
+                .as_ref()
+                .and_then(|section| section.upgrade())
+                .map(|section| (*section).borrow().uid)
+                .into_iter()
+                .collect()
+        }
+
+        _ => {
+            let section_name = utils::get_encapsulated_section_name(symbol_name);
+
+            layout.output_sections
+                .iter()
+                .filter(|output_section| Some(output_section.name.as_str()) == section_name)
+                .flat_map(|output_section| output_section.input_sections.iter())
+                .map(|section| (*section).borrow().uid)
+                .collect()
+        }
+    }
+}
+
+/// Escapes a DOT string literal.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
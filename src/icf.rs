@@ -0,0 +1,405 @@
+use std::{cell::RefCell, collections::{HashMap, HashSet}, rc::Rc};
+use crate::elf::{context::{Context, SymbolEntry}, file::{ElfRelocationKind, ElfRelocationTarget, ElfSection, ElfSectionKind}};
+use crate::merge::MergedSections;
+use crate::options::IcfMode;
+
+/// Resolved relocation target.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Target {
+    /// Section uid and offset.
+    Section(u64, usize),
+    /// Symbol not defined in input sections.
+    Symbol(String),
+    Absolute,
+}
+
+/// Relocation target compared between iterations.
+#[derive(PartialEq, Eq, Hash)]
+enum TargetKey {
+    /// Equivalence class of a candidate section and offset.
+    Class(usize, usize),
+    Other(Target),
+}
+
+/// Folds identical live code sections (identical code folding).
+///
+/// Sections are identical if they have the same flags, alignment and
+/// contents, and their relocations refer to the same targets or to
+/// identical sections. Classes of identical sections are refined until
+/// they don't change. Every class keeps its first section in input order,
+/// others are removed from `live_sections` and redirected to it.
+///
+/// In the safe mode sections with significant addresses are not folded.
+pub fn fold_identical_sections(
+    context: &Context,
+    live_sections: &mut Vec<Rc<RefCell<ElfSection>>>,
+    mode: IcfMode,
+    merged_sections: &mut MergedSections,
+) {
+    let live = live_sections
+        .iter()
+        .map(|section| (*section).borrow().uid)
+        .collect::<HashSet<_>>();
+
+    let candidates = context.objects
+        .iter()
+        .flat_map(|object| object.sections())
+        .filter(|section| {
+            let section = (**section).borrow();
+
+            live.contains(&section.uid) &&
+            section.is_alloc() &&
+            matches!(section.kind, ElfSectionKind::Code) &&
+            !section.uninitialized &&
+            section.data.is_some() &&
+            (mode == IcfMode::All || !section.address_significant)
+        })
+        .collect::<Vec<_>>();
+
+    let candidate_indices = candidates
+        .iter()
+        .enumerate()
+        .map(|(index, section)| ((**section).borrow().uid, index))
+        .collect::<HashMap<_, _>>();
+
+    let targets = candidates
+        .iter()
+        .map(|section| {
+            (**section).borrow().relocations
+                .iter()
+                .map(|relocation| get_target(context, &relocation.target))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    // initial classes by contents and relocation places
+
+    let mut keys = HashMap::new();
+
+    let mut classes = candidates
+        .iter()
+        .map(|section| {
+            let section = (**section).borrow();
+
+            let relocations = section.relocations
+                .iter()
+                .map(|relocation| {
+                    let relative = matches!(relocation.kind, ElfRelocationKind::Relative);
+                    (relocation.offset, relocation.size, relative)
+                })
+                .collect::<Vec<_>>();
+
+            let key = (section.flags, section.alignment, section.data.clone(), relocations);
+            let class_count = keys.len();
+
+            *keys.entry(key).or_insert(class_count)
+        })
+        .collect::<Vec<_>>();
+
+    let mut class_count = keys.len();
+
+    // refine classes by relocation targets
+
+    loop {
+        let mut keys = HashMap::new();
+
+        let refined_classes = (0..candidates.len())
+            .map(|index| {
+                let target_keys = targets[index]
+                    .iter()
+                    .map(|target| match target {
+                        Target::Section(uid, offset) => match candidate_indices.get(uid) {
+                            Some(&target_index) => TargetKey::Class(classes[target_index], *offset),
+                            None => TargetKey::Other(target.clone()),
+                        },
+
+                        _ => TargetKey::Other(target.clone()),
+                    })
+                    .collect::<Vec<_>>();
+
+                let refined_class_count = keys.len();
+
+                *keys
+                    .entry((classes[index], target_keys))
+                    .or_insert(refined_class_count)
+            })
+            .collect::<Vec<_>>();
+
+        classes = refined_classes;
+
+        if keys.len() == class_count {
+            break;
+        }
+
+        class_count = keys.len();
+    }
+
+    // fold classes into their first sections
+
+    let mut kept_sections = HashMap::new();
+
+    for (index, section) in candidates.iter().enumerate() {
+        let kept_index = *kept_sections
+            .entry(classes[index])
+            .or_insert(index);
+
+        if kept_index == index {
+            continue;
+        }
+
+        let section = (**section).borrow();
+        let kept_section = (*candidates[kept_index]).borrow();
+
+        log::debug!("fold section `{}` into `{}`", section.name, kept_section.name);
+
+        merged_sections.fold(section.uid, kept_section.uid);
+    }
+
+    live_sections.retain(|section| merged_sections.get_folded_target((**section).borrow().uid).is_none());
+}
+
+fn get_target(context: &Context, target: &ElfRelocationTarget) -> Target {
+    match target {
+        ElfRelocationTarget::Symbol(weak_symbol) => {
+            let symbol = weak_symbol.upgrade().expect("cannot get strong symbol");
+            let symbol = (*symbol).borrow();
+            let symbol_name = symbol.name.as_ref().expect("cannot get symbol name");
+
+            let Some(SymbolEntry::Resolved(resolved_symbol)) = context.symbol_map.get(symbol_name) else {
+                return Target::Symbol(symbol_name.to_owned());
+            };
+
+            let resolved_symbol = resolved_symbol.upgrade().expect("cannot get strong symbol");
+            let resolved_symbol = (*resolved_symbol).borrow();
+
+            match resolved_symbol.section.as_ref().and_then(|section| section.upgrade()) {
+                Some(section) => Target::Section((*section).borrow().uid, resolved_symbol.offset),
+                None => Target::Symbol(symbol_name.to_owned()),
+            }
+        }
+
+        ElfRelocationTarget::Section(weak_section) => {
+            let section = weak_section.upgrade().expect("got empty weak section");
+            let uid = (*section).borrow().uid;
+
+            Target::Section(uid, 0)
+        }
+
+        ElfRelocationTarget::Absolute => Target::Absolute,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use object::{write, Object, ObjectSymbol};
+    use object::{Architecture, BinaryFormat, Endianness, RelocationEncoding, RelocationKind, SectionKind, SymbolFlags, SymbolKind, SymbolScope};
+    use crate::elf::file::{ElfObjectFile, FileParser};
+    use super::*;
+
+    const SHT_LLVM_ADDRSIG: u32 = 0x6FFF_4C03;
+
+    /// Function in `.text.NAME`, `body` followed by `call` of every callee.
+    type Function = (&'static str, &'static [u8], &'static [&'static str]);
+
+    /// Builds an i386 object of the functions, `.llvm_addrsig` lists
+    /// `address_significant` functions if set.
+    fn build_object(functions: &[Function], address_significant: Option<&[&str]>) -> Vec<u8> {
+        let mut object = write::Object::new(BinaryFormat::Elf, Architecture::I386, Endianness::Little);
+
+        let symbols = functions
+            .iter()
+            .map(|&(name, body, calls)| {
+                let section = object.add_section(Vec::new(), format!(".text.{}", name).into_bytes(), SectionKind::Text);
+
+                let mut code = body.to_vec();
+                code.extend(calls.iter().flat_map(|_| [0xE8, 0, 0, 0, 0]));
+                code.push(0xC3);
+
+                object.append_section_data(section, &code, 16);
+
+                let symbol = object.add_symbol(write::Symbol {
+                    name: name.as_bytes().to_vec(),
+                    value: 0,
+                    size: code.len() as u64,
+                    kind: SymbolKind::Text,
+                    scope: SymbolScope::Linkage,
+                    weak: false,
+                    section: write::SymbolSection::Section(section),
+                    flags: SymbolFlags::None,
+                });
+
+                (section, symbol)
+            })
+            .collect::<Vec<_>>();
+
+        for (&(_, body, calls), &(section, _)) in functions.iter().zip(symbols.iter()) {
+            for (index, callee) in calls.iter().enumerate() {
+                let callee_index = functions.iter().position(|(name, ..)| name == callee).unwrap();
+
+                object.add_relocation(section, write::Relocation {
+                    offset: (body.len() + index * 5 + 1) as u64,
+                    size: 32,
+                    kind: RelocationKind::Relative,
+                    encoding: RelocationEncoding::Generic,
+                    symbol: symbols[callee_index].1,
+                    addend: -4,
+                }).unwrap();
+            }
+        }
+
+        let Some(address_significant) = address_significant else {
+            return object.write().unwrap();
+        };
+
+        // symbol indices are known after writing
+        let data = object.write().unwrap();
+        let file = object::File::parse(&*data).unwrap();
+        let mut table = Vec::new();
+
+        for symbol in file.symbols() {
+            if address_significant.contains(&symbol.name().unwrap()) {
+                let mut index = symbol.index().0;
+
+                loop {
+                    let byte = (index & 0x7F) as u8;
+                    index >>= 7;
+
+                    if index == 0 {
+                        table.push(byte);
+                        break;
+                    }
+
+                    table.push(byte | 0x80);
+                }
+            }
+        }
+
+        let section = object.add_section(Vec::new(), b".llvm_addrsig".to_vec(), SectionKind::Elf(SHT_LLVM_ADDRSIG));
+        object.append_section_data(section, &table, 1);
+
+        object.write().unwrap()
+    }
+
+    /// Returns folded sections and sections they are folded into.
+    fn fold(functions: &[Function], address_significant: Option<&[&str]>, mode: IcfMode) -> Vec<(String, String)> {
+        let mut context = Context::new();
+        let mut object_file = ElfObjectFile::new("test.o".to_owned(), None);
+
+        object_file.parse(&build_object(functions, address_significant), &mut context).unwrap();
+        context.objects.push(object_file);
+
+        let sections = context.objects[0].sections();
+        let mut live_sections = sections.clone();
+        let mut merged_sections = MergedSections::default();
+
+        fold_identical_sections(&context, &mut live_sections, mode, &mut merged_sections);
+
+        let get_name = |uid| {
+            sections
+                .iter()
+                .map(|section| (**section).borrow())
+                .find(|section| section.uid == uid)
+                .map(|section| section.name.to_owned())
+                .unwrap()
+        };
+
+        let folded = sections
+            .iter()
+            .filter_map(|section| {
+                let uid = (**section).borrow().uid;
+                let target = merged_sections.get_folded_target(uid)?;
+
+                Some((get_name(uid), get_name(target)))
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(live_sections.len() + folded.len(), sections.len());
+
+        folded
+    }
+
+    fn folded(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(section, target)| (format!(".text.{}", section), format!(".text.{}", target)))
+            .collect()
+    }
+
+    #[test]
+    fn identical_sections_with_identical_relocations_are_folded() {
+        let functions: &[Function] = &[
+            ("g", &[0x90], &[]),
+            ("f1", &[0x31, 0xC0], &["g"]),
+            ("f2", &[0x31, 0xC0], &["g"]),
+            ("f3", &[0x31, 0xDB], &["g"]),
+        ];
+
+        assert_eq!(fold(functions, None, IcfMode::All), folded(&[("f2", "f1")]));
+    }
+
+    #[test]
+    fn sections_calling_different_classes_are_not_folded() {
+        let functions: &[Function] = &[
+            ("g1", &[0x90], &[]),
+            ("g2", &[0x40], &[]),
+            ("f1", &[0x31, 0xC0], &["g1"]),
+            ("f2", &[0x31, 0xC0], &["g2"]),
+        ];
+
+        assert_eq!(fold(functions, None, IcfMode::All), folded(&[]));
+    }
+
+    #[test]
+    fn sections_calling_identical_sections_are_folded() {
+        let functions: &[Function] = &[
+            ("g1", &[0x90], &[]),
+            ("g2", &[0x90], &[]),
+            ("f1", &[0x31, 0xC0], &["g1"]),
+            ("f2", &[0x31, 0xC0], &["g2"]),
+        ];
+
+        assert_eq!(fold(functions, None, IcfMode::All), folded(&[("g2", "g1"), ("f2", "f1")]));
+    }
+
+    #[test]
+    fn safe_mode_keeps_address_significant_sections() {
+        let functions: &[Function] = &[
+            ("f1", &[0x90], &[]),
+            ("f2", &[0x90], &[]),
+            ("f3", &[0x90], &[]),
+        ];
+
+        assert_eq!(fold(functions, Some(&["f2"]), IcfMode::Safe), folded(&[("f3", "f1")]));
+        assert_eq!(fold(functions, Some(&["f1"]), IcfMode::Safe), folded(&[("f3", "f2")]));
+        assert_eq!(fold(functions, Some(&["f2"]), IcfMode::All), folded(&[("f2", "f1"), ("f3", "f1")]));
+        // without the table every address is significant
+        assert_eq!(fold(functions, None, IcfMode::Safe), folded(&[]));
+    }
+
+    #[test]
+    fn mutually_recursive_sections_are_folded() {
+        let functions: &[Function] = &[
+            ("a1", &[0x31, 0xC0], &["b1"]),
+            ("b1", &[0x31, 0xDB], &["a1"]),
+            ("a2", &[0x31, 0xC0], &["b2"]),
+            ("b2", &[0x31, 0xDB], &["a2"]),
+            // same contents, but calls a different cycle
+            ("a3", &[0x31, 0xC0], &["b3"]),
+            ("b3", &[0x31, 0xDB], &["c3"]),
+            ("c3", &[0x90], &["a3"]),
+        ];
+
+        assert_eq!(fold(functions, None, IcfMode::All), folded(&[("a2", "a1"), ("b2", "b1")]));
+    }
+
+    #[test]
+    fn self_recursive_sections_are_folded() {
+        let functions: &[Function] = &[
+            ("f1", &[0x31, 0xC0], &["f1"]),
+            ("f2", &[0x31, 0xC0], &["f2"]),
+            ("f3", &[0x31, 0xC0], &["f1"]),
+        ];
+
+        assert_eq!(fold(functions, None, IcfMode::All), folded(&[("f2", "f1"), ("f3", "f1")]));
+    }
+}
@@ -1,95 +1,123 @@
+use crate::error::{Error, LayoutError};
+use crate::layout::{align_up, Layout};
 use crate::writer::{Writer, Operation, BufferRegion};
 
+/// Stack reserved when the script doesn't define `__stack_top`.
+const DEFAULT_STACK_SIZE: usize = 4096;
+
 pub struct KosApplication<'a> {
     writer: Writer<'a>,
 }
 
 struct Header {
-    code_begin_offset: BufferRegion,
-    code_end_offset: BufferRegion,
-    memory_offset: BufferRegion,
-    stack_offset: BufferRegion,
+    entry_point: BufferRegion,
+    image_end: BufferRegion,
+    memory_size: BufferRegion,
+    stack_top: BufferRegion,
 }
 
 impl<'a> KosApplication<'a> {
+    /// Size of the MENUET01 header, the image is loaded at address 0.
+    pub const HEADER_SIZE: usize = 36;
+
     pub fn new(filename: &'a str) -> Self {
         Self {
             writer: Writer::new(filename)
         }
     }
 
-    pub fn get_offsets(&mut self, code_size: usize, data_size: usize) -> (usize, usize) {
-        let code = vec![0; code_size];
-        let data = vec![0; data_size];
-        self.build(code, data, false)
-    }
-
-    pub fn build(&mut self, code: Vec<u8>, data: Vec<u8>, create_file: bool) -> (usize, usize) {
-        
+    /// Writes the header and output sections at their addresses.
+    ///
+    /// `NOBITS` sections occupy memory only, the image ends with the last
+    /// section that has contents. The initial stack pointer is taken from
+    /// the `__stack_top` symbol, otherwise a 4 KB stack is placed after the
+    /// end of the memory.
+    pub fn build(&mut self, layout: &Layout) -> Result<(), Error> {
         self.writer.clear();
 
         let header = self.write_header();
-        
-        let writer = &mut self.writer;     
-        
-        // emit code
-        writer.append_padding(4, None);
 
-        let code_begin_offset = writer.offset();
+        let writer = &mut self.writer;
 
-        writer.insert_buffer(Operation::Append, &code);
+        let mut output_sections = layout.output_sections.iter().collect::<Vec<_>>();
+        output_sections.sort_by_key(|output_section| output_section.address);
 
-        let code_end_offset = writer.offset();
+        // check overlapping
 
-        // fix header for code
+        let mut previous_end = Self::HEADER_SIZE;
 
-        writer.insert_u32(
-            Operation::Update(header.code_begin_offset),
-            code_begin_offset as u32
-        );
+        for output_section in output_sections.iter() {
+            if output_section.address < previous_end {
+                return Err(Error::Layout(LayoutError::OverlappingSections {
+                    section: output_section.name.to_owned(),
+                    address: output_section.address,
+                    previous_end,
+                }));
+            }
 
-        writer.insert_u32(
-            Operation::Update(header.code_end_offset),
-            code_end_offset as u32
-        );
+            previous_end = output_section.address + output_section.size;
+        }
 
-        // emit stack
+        // emit sections
 
-        writer.append_padding(16, None);
-        writer.append_dup(0, 4096); // now = 4KB, 4 MB = 4 * 256 * 4096, 
-        writer.append_padding(16, None);
+        let file_sections = output_sections
+            .iter()
+            .rposition(|output_section| !output_section.nobits)
+            .map_or(0, |last| last + 1);
 
-        let stack_offset = writer.offset();
+        for output_section in output_sections[..file_sections].iter() {
+            writer.append_dup(0, output_section.address - writer.offset());
 
-        // fix header for stack
+            if output_section.nobits {
+                writer.append_dup(0, output_section.size);
+            } else {
+                writer.insert_buffer(Operation::Append, &output_section.data);
+            }
+        }
 
-        writer.insert_u32(
-            Operation::Update(header.stack_offset),
-            stack_offset as u32
-        );
+        let image_end = writer.offset();
+        let mut memory_end = layout.end_address.max(image_end);
+
+        // stack
 
-        // emit data
+        let stack_top = match layout.symbols.get("__stack_top") {
+            Some(stack_top) => *stack_top,
 
-        let memory_begin_offset = writer.offset();
+            None => {
+                memory_end = align_up(memory_end, 16).saturating_add(DEFAULT_STACK_SIZE);
+                memory_end
+            }
+        };
 
-        writer.insert_buffer(Operation::Append, &data);
+        memory_end = memory_end.max(stack_top);
 
-        let memory_end_offset = writer.offset();
+        // fix header
 
-        // fix header for data
+        writer.insert_u32(
+            Operation::Update(header.entry_point),
+            to_u32(layout.entry_address, "entry point")?
+        );
+
+        writer.insert_u32(
+            Operation::Update(header.image_end),
+            to_u32(image_end, "end of the image")?
+        );
+
+        writer.insert_u32(
+            Operation::Update(header.memory_size),
+            to_u32(memory_end, "end of the memory")?
+        );
 
         writer.insert_u32(
-            Operation::Update(header.memory_offset),
-            memory_end_offset as u32
+            Operation::Update(header.stack_top),
+            to_u32(stack_top, "stack top")?
         );
 
         // write to file
 
-        if create_file {
-            self.writer.write();
-        }
+        self.writer.write()?;
 
-        (code_begin_offset, memory_begin_offset)
+        Ok(())
     }
 
     fn write_header(&mut self) -> Header {
@@ -99,37 +127,47 @@ impl<'a> KosApplication<'a> {
         writer.insert_u32(Operation::Append, 1); // version
 
         // code start (entry point)
-        let code_begin_offset = writer.insert_u32(
-            Operation::Append, 
+        let entry_point = writer.insert_u32(
+            Operation::Append,
             0
-        ); 
- 
-        // code_end
-        let code_end_offset = writer.insert_u32(
-            Operation::Append, 
+        );
+
+        // end of the image in the file
+        let image_end = writer.insert_u32(
+            Operation::Append,
             0
         );
 
-         // data region offset
-        let memory_offset = writer.insert_u32(
-            Operation::Append, 
+        // memory size
+        let memory_size = writer.insert_u32(
+            Operation::Append,
             0
         );
 
         // stack region offset (initial ESP)
-        let stack_offset = writer.insert_u32(
-            Operation::Append, 
+        let stack_top = writer.insert_u32(
+            Operation::Append,
             0
         );
 
         writer.insert_u32(Operation::Append, 0); // params
         writer.insert_u32(Operation::Append, 0); // icon
 
+        debug_assert_eq!(writer.offset(), Self::HEADER_SIZE);
+
         Header {
-            code_begin_offset,
-            code_end_offset,
-            memory_offset,
-            stack_offset
+            entry_point,
+            image_end,
+            memory_size,
+            stack_top
         }
     }
 }
+
+/// Header fields are 32-bit.
+fn to_u32(value: usize, what: &str) -> Result<u32, LayoutError> {
+    u32::try_from(value).map_err(|_| LayoutError::AddressOverflow {
+        what: what.to_owned(),
+        address: value as u64,
+    })
+}
@@ -0,0 +1,175 @@
+use std::{cell::RefCell, collections::{HashMap, HashSet}, rc::Rc};
+use object::elf;
+use crate::elf::{context::Context, file::ElfSection};
+use crate::script::{Script, Statement};
+
+/// Coarse section class used to place orphan sections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SectionClass {
+    Code,
+    ReadOnlyData,
+    Data,
+    Uninitialized,
+}
+
+impl SectionClass {
+    pub fn of(section: &ElfSection) -> Self {
+        if section.flags & (elf::SHF_EXECINSTR as u64) != 0 {
+            Self::Code
+        } else if section.uninitialized {
+            Self::Uninitialized
+        } else if section.flags & (elf::SHF_WRITE as u64) != 0 {
+            Self::Data
+        } else {
+            Self::ReadOnlyData
+        }
+    }
+}
+
+/// Sections with the same name not matched by any input section description.
+pub struct OrphanSection {
+    pub name: String,
+    pub sections: Vec<Rc<RefCell<ElfSection>>>,
+    /// Output section to place the orphan after, `None` to place at the end.
+    pub anchor: Option<String>,
+}
+
+/// Assignment of allocatable input sections to output sections.
+pub struct SectionMapping {
+    /// Input section description id -> matched sections in input order.
+    pub descriptions: Vec<Vec<Rc<RefCell<ElfSection>>>>,
+    pub orphans: Vec<OrphanSection>,
+    /// Sections matched by `KEEP(...)`.
+    pub kept: Vec<Rc<RefCell<ElfSection>>>,
+    /// Uids of sections placed into `/DISCARD/`.
+    pub discarded: HashSet<u64>,
+    /// Output section name -> input sections.
+    output_sections: HashMap<String, Vec<Rc<RefCell<ElfSection>>>>,
+}
+
+impl SectionMapping {
+    /// Matches every allocatable input section against the script.
+    ///
+    /// A section goes to the first matching input section description
+    /// in script order.
+    pub fn new(context: &Context, script: &Script) -> Self {
+        let descriptions = script
+            .input_section_descriptions()
+            .collect::<Vec<_>>();
+
+        let mut mapping = Self {
+            descriptions: vec![Vec::new(); descriptions.len()],
+            orphans: Vec::new(),
+            kept: Vec::new(),
+            discarded: HashSet::new(),
+            output_sections: HashMap::new(),
+        };
+
+        for object in context.objects.iter() {
+            for section in object.sections() {
+                let section_ref = (*section).borrow();
+
+                if !section_ref.is_alloc() {
+                    continue;
+                }
+
+                if section_ref.discarded {
+                    mapping.discarded.insert(section_ref.uid);
+                    continue;
+                }
+
+                let filename = section_ref.file
+                    .upgrade()
+                    .map(|file| (*file).borrow().filename.to_owned())
+                    .unwrap_or_default();
+
+                let description = descriptions
+                    .iter()
+                    .find(|(_, description)| description.matches(&filename, &section_ref.name, section_ref.flags));
+
+                let output_section_name = if let Some((output_section, description)) = description {
+                    if output_section.is_discard() {
+                        mapping.discarded.insert(section_ref.uid);
+                        continue;
+                    }
+
+                    if description.keep {
+                        mapping.kept.push(Rc::clone(&section));
+                    }
+
+                    mapping.descriptions[description.id].push(Rc::clone(&section));
+
+                    output_section.name.to_owned()
+                } else {
+                    let orphan_index = mapping.orphans
+                        .iter()
+                        .position(|orphan| orphan.name == section_ref.name);
+
+                    let orphan_index = orphan_index.unwrap_or_else(|| {
+                        mapping.orphans.push(OrphanSection {
+                            name: section_ref.name.to_owned(),
+                            sections: Vec::new(),
+                            anchor: None,
+                        });
+
+                        mapping.orphans.len() - 1
+                    });
+
+                    mapping.orphans[orphan_index].sections.push(Rc::clone(&section));
+
+                    section_ref.name.to_owned()
+                };
+
+                log::trace!("map section `{}` ({}) to `{}`", section_ref.name, filename, output_section_name);
+
+                mapping.output_sections
+                    .entry(output_section_name)
+                    .or_default()
+                    .push(Rc::clone(&section));
+            }
+        }
+
+        // classify script output sections by their first input
+
+        let mut output_section_classes = Vec::new();
+
+        for statement in script.statements.iter() {
+            if let Statement::OutputSection(output_section) = statement {
+                let inputs = mapping.output_sections
+                    .entry(output_section.name.to_owned())
+                    .or_default();
+
+                if let Some(first) = inputs.first().filter(|_| !output_section.is_discard()) {
+                    output_section_classes.push((output_section.name.to_owned(), SectionClass::of(&(**first).borrow())));
+                }
+            }
+        }
+
+        for orphan in mapping.orphans.iter_mut() {
+            let class = SectionClass::of(&(*orphan.sections[0]).borrow());
+
+            let same_class = output_section_classes
+                .iter()
+                .rfind(|(_, output_section_class)| *output_section_class == class);
+
+            let lower_class = output_section_classes
+                .iter()
+                .rfind(|(_, output_section_class)| *output_section_class < class);
+
+            orphan.anchor = same_class
+                .or(lower_class)
+                .map(|(name, _)| name.to_owned());
+
+            log::trace!("orphan section `{}` ({:?}) is placed after {:?}", orphan.name, class, orphan.anchor);
+        }
+
+        mapping
+    }
+
+    /// Returns input sections mapped to the output section.
+    pub fn get_output_section_inputs(&self, name: &str) -> Option<&[Rc<RefCell<ElfSection>>]> {
+        self.output_sections
+            .get(name)
+            .map(|sections| sections.as_slice())
+    }
+}
@@ -0,0 +1,693 @@
+mod mapping;
+
+pub use mapping::SectionMapping;
+
+use std::{cell::RefCell, collections::{HashMap, HashSet}, rc::Rc};
+use crate::elf::{context::{Context, SymbolEntry}, file::ElfSection, utils};
+use crate::error::LayoutError;
+use crate::merge::MergedSections;
+use crate::script::{
+    Assignment, AssignmentOperator, BinaryOperator, Expression, OutputSectionCommand,
+    OutputSectionDescription, Script, Statement, UnaryOperator, LOCATION_COUNTER,
+};
+
+pub struct OutputSection {
+    pub name: String,
+    pub address: usize,
+    pub size: usize,
+    pub alignment: usize,
+    /// Big-endian pattern for gaps between input sections.
+    pub fill: Vec<u8>,
+    /// Occupies memory only, has no contents in the file.
+    pub nobits: bool,
+    /// Placed input sections in address order.
+    pub input_sections: Vec<Rc<RefCell<ElfSection>>>,
+    /// Section contents, filled by `Layout::emit`.
+    pub data: Vec<u8>,
+}
+
+pub struct Layout {
+    pub output_sections: Vec<OutputSection>,
+    /// Input section uid -> address.
+    pub section_addresses: HashMap<u64, usize>,
+    /// Symbols defined by the script and `__start_`/`__stop_` symbols.
+    pub symbols: HashMap<String, usize>,
+    pub entry: String,
+    pub entry_address: usize,
+    /// Final location counter or the end of the last output section.
+    pub end_address: usize,
+    pub merged_sections: MergedSections,
+}
+
+impl Layout {
+    /// Assigns addresses to live sections by evaluating the script.
+    ///
+    /// The script is evaluated twice: the first pass collects addresses and
+    /// sizes, so the second one can resolve forward references.
+    pub fn new(
+        context: &Context,
+        script: &Script,
+        mapping: &SectionMapping,
+        live_sections: &[Rc<RefCell<ElfSection>>],
+        merged_sections: MergedSections,
+        header_size: usize,
+    ) -> Result<Self, LayoutError> {
+        let live = live_sections
+            .iter()
+            .map(|section| (*section).borrow().uid)
+            .collect::<HashSet<_>>();
+
+        let mut previous = None;
+
+        for strict in [false, true] {
+            let mut evaluator = Evaluator {
+                context,
+                mapping,
+                live: &live,
+                merged_sections: &merged_sections,
+                header_size,
+                strict,
+                previous: previous.as_ref(),
+                location_counter: 0,
+                section_start: None,
+                symbols: HashMap::new(),
+                section_addresses: HashMap::new(),
+                output_section_regions: HashMap::new(),
+                output_sections: Vec::new(),
+            };
+
+            evaluator.run(script)?;
+
+            previous = Some(evaluator.finish(script));
+        }
+
+        let mut layout = previous.expect("layout is not evaluated");
+
+        layout.merged_sections = merged_sections;
+        layout.entry_address = layout
+            .get_symbol_address(context, &layout.entry)
+            .ok_or_else(|| LayoutError::UnresolvedEntry(layout.entry.to_owned()))?;
+
+        Ok(layout)
+    }
+
+    /// Fills output sections with the contents of their input sections.
+    pub fn emit(&mut self) {
+        for output_section in self.output_sections.iter_mut() {
+            if output_section.nobits {
+                continue;
+            }
+
+            let mut data = output_section.fill
+                .iter()
+                .copied()
+                .cycle()
+                .take(output_section.size)
+                .collect::<Vec<_>>();
+
+            for input_section in output_section.input_sections.iter() {
+                let input_section = (*input_section).borrow();
+
+                let offset = self.section_addresses[&input_section.uid] - output_section.address;
+                let section_data = input_section.data.as_deref().unwrap_or_default();
+                let end_offset = offset + input_section.size;
+
+                data[offset..(offset + section_data.len())].copy_from_slice(section_data);
+                data[(offset + section_data.len()).min(end_offset)..end_offset].fill(0);
+            }
+
+            output_section.data = data;
+        }
+    }
+
+    pub fn get_section_address(&self, section: &ElfSection) -> usize {
+        *self.section_addresses
+            .get(&section.uid)
+            .unwrap_or_else(|| panic!("section `{}` is not placed", section.name))
+    }
+
+    /// Returns the address of the byte at `offset` of the original input
+    /// section, offsets in deduplicated sections are translated. Returns
+    /// `None` if the section is not placed, e.g. it is not `SHF_ALLOC`.
+    pub fn get_section_offset_address(&self, section: &ElfSection, offset: usize) -> Option<usize> {
+        let (uid, offset) = self.merged_sections.translate(section.uid, offset);
+
+        self.section_addresses
+            .get(&uid)
+            .map(|section_address| section_address.wrapping_add(offset))
+    }
+
+    /// Returns placed symbols, local ones included, with their addresses
+    /// by the uid of the input section. Symbols of folded sections are
+    /// listed with the kept section.
+    pub fn get_section_symbols<'c>(&self, context: &'c Context) -> HashMap<u64, Vec<(usize, &'c str)>> {
+        let mut section_symbols = HashMap::<u64, Vec<(usize, &str)>>::new();
+
+        for (symbol_name, symbol_entry) in context.symbol_map.iter() {
+            let SymbolEntry::Resolved(weak_symbol) = symbol_entry else {
+                continue;
+            };
+
+            let symbol = weak_symbol.upgrade().expect("cannot get symbol");
+            let symbol = (*symbol).borrow();
+
+            let Some(section) = symbol.section.as_ref().and_then(|section| section.upgrade()) else {
+                continue;
+            };
+
+            let Some(address) = self.get_symbol_address(context, symbol_name) else {
+                continue;
+            };
+
+            let uid = (*section).borrow().uid;
+            let uid = self.merged_sections.get_folded_target(uid).unwrap_or(uid);
+
+            section_symbols
+                .entry(uid)
+                .or_default()
+                .push((address, symbol_name));
+        }
+
+        section_symbols
+    }
+
+    pub fn get_symbol_address(&self, context: &Context, symbol_name: &str) -> Option<usize> {
+        self.get_symbol_offset_address(context, symbol_name, 0)
+    }
+
+    /// Returns the address of the symbol plus `offset`, the offset is
+    /// translated when the symbol is defined in a deduplicated section.
+    /// Values of absolute (`SHN_ABS`) symbols are addresses. Returns `None`
+    /// if the symbol is not defined or its section is not placed.
+    pub fn get_symbol_offset_address(&self, context: &Context, symbol_name: &str, offset: usize) -> Option<usize> {
+        if let Some(address) = self.symbols.get(symbol_name) {
+            return Some(address.wrapping_add(offset));
+        }
+
+        match context.symbol_map.get(symbol_name)? {
+            SymbolEntry::Resolved(weak_symbol) => {
+                let symbol = weak_symbol.upgrade().expect("cannot get strong symbol");
+                let symbol = (*symbol).borrow();
+
+                if symbol.absolute {
+                    return Some(symbol.offset.wrapping_add(offset));
+                }
+
+                let section = symbol.section.as_ref()?.upgrade().expect("got empty weak section");
+                let section = (*section).borrow();
+
+                self.get_section_offset_address(&section, symbol.offset.wrapping_add(offset))
+            }
+
+            // undefined weak references resolve to 0
+            SymbolEntry::Unresolved { weak: true } => Some(offset),
+            SymbolEntry::Unresolved { weak: false } => None,
+        }
+    }
+}
+
+/// Single pass of the script evaluation.
+struct Evaluator<'a> {
+    context: &'a Context,
+    mapping: &'a SectionMapping,
+    live: &'a HashSet<u64>,
+    merged_sections: &'a MergedSections,
+    header_size: usize,
+    /// Undefined references are errors in the strict pass.
+    strict: bool,
+    previous: Option<&'a Layout>,
+    location_counter: usize,
+    /// Start address of the output section being evaluated.
+    section_start: Option<usize>,
+    symbols: HashMap<String, usize>,
+    section_addresses: HashMap<u64, usize>,
+    /// Output section name -> (address, size).
+    output_section_regions: HashMap<String, (usize, usize)>,
+    output_sections: Vec<OutputSection>,
+}
+
+impl<'a> Evaluator<'a> {
+    fn run(&mut self, script: &Script) -> Result<(), LayoutError> {
+        for statement in script.statements.iter() {
+            match statement {
+                Statement::Assignment(assignment) => self.assign(assignment)?,
+                Statement::Assert(assert) => self.check(&assert.expression, &assert.message)?,
+                Statement::OutputSection(output_section) => self.place_output_section(output_section)?,
+            }
+        }
+
+        self.place_orphan_sections(None)
+    }
+
+    /// Places orphan sections anchored to the output section.
+    fn place_orphan_sections(&mut self, anchor: Option<&str>) -> Result<(), LayoutError> {
+        for orphan in self.mapping.orphans.iter() {
+            if orphan.anchor.as_deref() != anchor {
+                continue;
+            }
+
+            let sections = orphan.sections
+                .iter()
+                .filter(|section| self.live.contains(&(***section).borrow().uid))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if sections.is_empty() {
+                continue;
+            }
+
+            log::debug!("place orphan section `{}`", orphan.name);
+
+            let alignment = Self::get_max_alignment(&sections);
+            let nobits = sections.iter().all(|section| (**section).borrow().uninitialized);
+
+            let address = self.check_address(align_up(self.location_counter, alignment) as u64, || {
+                format!("output section `{}`", orphan.name)
+            })?;
+
+            self.begin_output_section(address);
+            self.place_input_sections(&sections)?;
+            self.end_output_section(&orphan.name, alignment, sections, vec![0], nobits);
+        }
+
+        Ok(())
+    }
+
+    fn finish(self, script: &Script) -> Layout {
+        let entry = script.entry
+            .clone()
+            .unwrap_or_else(|| "_start".to_owned());
+
+        let end_address = self.output_sections
+            .iter()
+            .map(|output_section| output_section.address + output_section.size)
+            .fold(self.location_counter, usize::max);
+
+        Layout {
+            output_sections: self.output_sections,
+            section_addresses: self.section_addresses,
+            symbols: self.symbols,
+            entry,
+            entry_address: 0,
+            end_address,
+            merged_sections: MergedSections::default(),
+        }
+    }
+
+    fn place_output_section(&mut self, description: &OutputSectionDescription) -> Result<(), LayoutError> {
+        if description.is_discard() {
+            return Ok(());
+        }
+
+        let sections = description.commands
+            .iter()
+            .filter_map(|command| match command {
+                OutputSectionCommand::InputSection(input_section) => Some(input_section.id),
+                _ => None,
+            })
+            .flat_map(|id| self.mapping.descriptions[id].iter())
+            .filter(|section| self.live.contains(&(***section).borrow().uid))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let alignment = match description.alignment {
+            Some(ref alignment) => self.evaluate(alignment)? as usize,
+            None => 1,
+        }
+        .max(Self::get_max_alignment(&sections));
+
+        let address = match description.address {
+            Some(ref address) => self.evaluate(address)?,
+            None => align_up(self.location_counter, alignment) as u64,
+        };
+
+        let address = self.check_address(address, || format!("output section `{}`", description.name))?;
+
+        self.begin_output_section(address);
+
+        let mut placed_sections = Vec::new();
+
+        for command in description.commands.iter() {
+            match command {
+                OutputSectionCommand::Assignment(assignment) => {
+                    self.assign(assignment)?;
+                }
+
+                OutputSectionCommand::Assert(assert) => {
+                    self.check(&assert.expression, &assert.message)?;
+                }
+
+                OutputSectionCommand::InputSection(input_section) => {
+                    let sections = self.mapping.descriptions[input_section.id]
+                        .iter()
+                        .filter(|section| self.live.contains(&(***section).borrow().uid))
+                        .cloned()
+                        .collect::<Vec<_>>();
+
+                    self.place_input_sections(&sections)?;
+                    placed_sections.extend(sections);
+                }
+            }
+        }
+
+        let fill = match description.fill {
+            Some(ref fill) => get_fill_pattern(self.evaluate(fill)?),
+            None => vec![0],
+        };
+
+        let nobits = description.noload || (
+            !placed_sections.is_empty() &&
+            placed_sections.iter().all(|section| (**section).borrow().uninitialized)
+        );
+
+        self.end_output_section(&description.name, alignment, placed_sections, fill, nobits);
+        self.place_orphan_sections(Some(&description.name))
+    }
+
+    fn begin_output_section(&mut self, address: usize) {
+        self.location_counter = address;
+        self.section_start = Some(address);
+    }
+
+    fn place_input_sections(&mut self, sections: &[Rc<RefCell<ElfSection>>]) -> Result<(), LayoutError> {
+        for section in sections.iter() {
+            let section = (**section).borrow();
+
+            let address = align_up(self.location_counter, section.alignment);
+
+            let end = self.check_address(address as u64 + section.size as u64, || {
+                format!("section `{}` from `{}`", section.name, section.get_file_name())
+            })?;
+
+            self.section_addresses.insert(section.uid, address);
+            self.location_counter = end;
+        }
+
+        Ok(())
+    }
+
+    fn end_output_section(
+        &mut self,
+        name: &str,
+        alignment: usize,
+        input_sections: Vec<Rc<RefCell<ElfSection>>>,
+        fill: Vec<u8>,
+        nobits: bool,
+    ) {
+        let address = self.section_start
+            .take()
+            .expect("output section is not started");
+
+        let size = self.location_counter - address;
+
+        self.output_section_regions.insert(name.to_owned(), (address, size));
+
+        if utils::is_c_identifier(name) {
+            self.define_linker_symbol(&format!("__start_{}", name), address);
+            self.define_linker_symbol(&format!("__stop_{}", name), address + size);
+        }
+
+        if input_sections.is_empty() && size == 0 {
+            return;
+        }
+
+        self.output_sections.push(OutputSection {
+            name: name.to_owned(),
+            address,
+            size,
+            alignment,
+            fill,
+            nobits,
+            input_sections,
+            data: Vec::new(),
+        });
+    }
+
+    fn get_max_alignment(sections: &[Rc<RefCell<ElfSection>>]) -> usize {
+        sections
+            .iter()
+            .map(|section| (**section).borrow().alignment)
+            .fold(1, usize::max)
+    }
+
+    /// Defines a symbol only if input files reference it without a definition.
+    fn define_linker_symbol(&mut self, name: &str, value: usize) {
+        if let Some(SymbolEntry::Unresolved { .. }) = self.context.symbol_map.get(name) {
+            self.symbols.insert(name.to_owned(), value);
+        }
+    }
+
+    fn assign(&mut self, assignment: &Assignment) -> Result<(), LayoutError> {
+        let value = self.evaluate(&assignment.expression)?;
+
+        if assignment.symbol == LOCATION_COUNTER {
+            let value = match (assignment.operator, self.section_start) {
+                // constant addresses are relative to the output section start
+                (AssignmentOperator::Assign, Some(section_start)) if assignment.expression.is_constant() => {
+                    (section_start as u64).saturating_add(value)
+                }
+
+                (operator, _) => apply_assignment(operator, self.location_counter as u64, value),
+            };
+
+            let value = self.check_address(value, || "location counter".to_owned())?;
+
+            if self.section_start.is_some() && value < self.location_counter {
+                return Err(LayoutError::LocationCounterBackwards {
+                    from: self.location_counter,
+                    to: value,
+                });
+            }
+
+            self.location_counter = value;
+            return Ok(());
+        }
+
+        if assignment.provide {
+            let value = self.check_address(value, || format!("symbol `{}`", assignment.symbol))?;
+
+            self.define_linker_symbol(&assignment.symbol, value);
+            return Ok(());
+        }
+
+        let current_value = self.symbols
+            .get(&assignment.symbol)
+            .copied()
+            .unwrap_or_default();
+
+        let value = apply_assignment(assignment.operator, current_value as u64, value);
+        let value = self.check_address(value, || format!("symbol `{}`", assignment.symbol))?;
+
+        self.symbols.insert(assignment.symbol.to_owned(), value);
+
+        Ok(())
+    }
+
+    /// Checks that an address fits the 32-bit image. The first pass may
+    /// compute garbage from forward references, it saturates instead.
+    fn check_address(&self, address: u64, what: impl FnOnce() -> String) -> Result<usize, LayoutError> {
+        match u32::try_from(address) {
+            Ok(address) => Ok(address as usize),
+            Err(_) if !self.strict => Ok(u32::MAX as usize),
+            Err(_) => Err(LayoutError::AddressOverflow { what: what(), address }),
+        }
+    }
+
+    fn check(&self, expression: &Expression, message: &str) -> Result<(), LayoutError> {
+        if self.strict && self.evaluate(expression)? == 0 {
+            return Err(LayoutError::AssertionFailed(message.to_owned()));
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(&self, expression: &Expression) -> Result<u64, LayoutError> {
+        let value = match expression {
+            Expression::Number(value) => *value,
+
+            Expression::Symbol(name) => self.get_symbol_value(name)?,
+
+            Expression::LocationCounter => self.location_counter as u64,
+
+            Expression::Unary(operator, operand) => {
+                let operand = self.evaluate(operand)?;
+
+                match operator {
+                    UnaryOperator::Negate => operand.wrapping_neg(),
+                    UnaryOperator::Not => (operand == 0) as u64,
+                    UnaryOperator::BitwiseNot => !operand,
+                }
+            }
+
+            Expression::Binary(operator, left, right) => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+
+                match operator {
+                    BinaryOperator::Multiply => left.wrapping_mul(right),
+                    BinaryOperator::Divide => self.divide(left, right, u64::checked_div)?,
+                    BinaryOperator::Remainder => self.divide(left, right, u64::checked_rem)?,
+                    BinaryOperator::Add => left.wrapping_add(right),
+                    BinaryOperator::Subtract => left.wrapping_sub(right),
+                    BinaryOperator::ShiftLeft => left.wrapping_shl(right as u32),
+                    BinaryOperator::ShiftRight => left.wrapping_shr(right as u32),
+                    BinaryOperator::Less => (left < right) as u64,
+                    BinaryOperator::LessOrEqual => (left <= right) as u64,
+                    BinaryOperator::Greater => (left > right) as u64,
+                    BinaryOperator::GreaterOrEqual => (left >= right) as u64,
+                    BinaryOperator::Equal => (left == right) as u64,
+                    BinaryOperator::NotEqual => (left != right) as u64,
+                    BinaryOperator::BitwiseAnd => left & right,
+                    BinaryOperator::BitwiseOr => left | right,
+                    BinaryOperator::And => (left != 0 && right != 0) as u64,
+                    BinaryOperator::Or => (left != 0 || right != 0) as u64,
+                }
+            }
+
+            Expression::Conditional(condition, left, right) => {
+                if self.evaluate(condition)? != 0 {
+                    self.evaluate(left)?
+                } else {
+                    self.evaluate(right)?
+                }
+            }
+
+            Expression::Align(expression, alignment) => {
+                let value = match expression {
+                    Some(expression) => self.evaluate(expression)?,
+                    None => self.location_counter as u64,
+                };
+
+                align_up(value as usize, self.evaluate(alignment)? as usize) as u64
+            }
+
+            Expression::Absolute(expression) => self.evaluate(expression)?,
+
+            Expression::Address(name) => self.get_output_section_region(name)?.0 as u64,
+
+            Expression::SizeOf(name) => self.get_output_section_region(name)?.1 as u64,
+
+            Expression::SizeOfHeaders => self.header_size as u64,
+
+            Expression::Defined(name) => {
+                let defined = self.symbols.contains_key(name) ||
+                    matches!(self.context.symbol_map.get(name), Some(SymbolEntry::Resolved(_)));
+
+                defined as u64
+            }
+
+            Expression::Max(left, right) => self.evaluate(left)?.max(self.evaluate(right)?),
+
+            Expression::Min(left, right) => self.evaluate(left)?.min(self.evaluate(right)?),
+        };
+
+        Ok(value)
+    }
+
+    fn divide(&self, left: u64, right: u64, operation: fn(u64, u64) -> Option<u64>) -> Result<u64, LayoutError> {
+        match operation(left, right) {
+            Some(value) => Ok(value),
+            None if !self.strict => Ok(0),
+            None => Err(LayoutError::DivisionByZero),
+        }
+    }
+
+    fn get_symbol_value(&self, name: &str) -> Result<u64, LayoutError> {
+        if let Some(value) = self.symbols.get(name) {
+            return Ok(*value as u64);
+        }
+
+        if let Some(SymbolEntry::Resolved(weak_symbol)) = self.context.symbol_map.get(name) {
+            let symbol = weak_symbol.upgrade().expect("cannot get strong symbol");
+            let symbol = (*symbol).borrow();
+
+            let address = match symbol.section.as_ref().and_then(|section| section.upgrade()) {
+                Some(section) => {
+                    let (uid, offset) = self.merged_sections.translate((*section).borrow().uid, symbol.offset);
+
+                    self.section_addresses
+                        .get(&uid)
+                        .or_else(|| self.previous.and_then(|previous| previous.section_addresses.get(&uid)))
+                        .map(|section_address| section_address.wrapping_add(offset))
+                }
+
+                None if symbol.absolute => Some(symbol.offset),
+                None => None,
+            };
+
+            if let Some(address) = address {
+                return Ok(address as u64);
+            }
+        }
+
+        if let Some(value) = self.previous.and_then(|previous| previous.symbols.get(name)) {
+            return Ok(*value as u64);
+        }
+
+        // undefined weak references resolve to 0
+        let weak_undefined = matches!(self.context.symbol_map.get(name), Some(SymbolEntry::Unresolved { weak: true }));
+
+        if self.strict && !weak_undefined {
+            return Err(LayoutError::UndefinedSymbol(name.to_owned()));
+        }
+
+        Ok(0)
+    }
+
+    fn get_output_section_region(&self, name: &str) -> Result<(usize, usize), LayoutError> {
+        let region = self.output_section_regions
+            .get(name)
+            .copied()
+            .or_else(|| {
+                self.previous.and_then(|previous| {
+                    previous.output_sections
+                        .iter()
+                        .find(|output_section| output_section.name == name)
+                        .map(|output_section| (output_section.address, output_section.size))
+                })
+            });
+
+        match region {
+            Some(region) => Ok(region),
+            None if !self.strict => Ok((0, 0)),
+            None => Err(LayoutError::UndefinedOutputSection(name.to_owned())),
+        }
+    }
+}
+
+fn apply_assignment(operator: AssignmentOperator, current_value: u64, value: u64) -> u64 {
+    match operator {
+        AssignmentOperator::Assign => value,
+        AssignmentOperator::Add => current_value.wrapping_add(value),
+        AssignmentOperator::Subtract => current_value.wrapping_sub(value),
+        AssignmentOperator::Multiply => current_value.wrapping_mul(value),
+        AssignmentOperator::Divide => current_value.checked_div(value).unwrap_or_default(),
+        AssignmentOperator::ShiftLeft => current_value.wrapping_shl(value as u32),
+        AssignmentOperator::ShiftRight => current_value.wrapping_shr(value as u32),
+        AssignmentOperator::And => current_value & value,
+        AssignmentOperator::Or => current_value | value,
+    }
+}
+
+/// Converts a fill value to the shortest big-endian pattern,
+/// `=0x90` fills with `90`, `=0x9090CCCC` with `90 90 CC CC`.
+fn get_fill_pattern(value: u64) -> Vec<u8> {
+    let bytes = (value as u32).to_be_bytes();
+
+    let length = match value {
+        0..=0xFF => 1,
+        0x100..=0xFFFF => 2,
+        _ => 4,
+    };
+
+    bytes[(4 - length)..].to_vec()
+}
+
+/// Saturates on overflow, so address checks catch it.
+pub fn align_up(value: usize, alignment: usize) -> usize {
+    if alignment <= 1 {
+        value
+    } else {
+        value.checked_next_multiple_of(alignment).unwrap_or(usize::MAX)
+    }
+}
@@ -0,0 +1,21 @@
+pub mod error;
+pub mod reader;
+pub mod archive;
+pub mod elf;
+pub mod writer;
+pub mod kos_application;
+pub mod gc;
+pub mod graph;
+pub mod layout;
+pub mod map;
+pub mod report;
+pub mod reproduce;
+pub mod size;
+pub mod stats;
+pub mod merge;
+pub mod icf;
+pub mod undefined;
+pub mod options;
+pub mod relocation;
+pub mod script;
+pub mod trace;
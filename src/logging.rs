@@ -1,42 +1,50 @@
-use log4rs::append::{console::ConsoleAppender, file::FileAppender};
-use log4rs::filter::threshold::ThresholdFilter;
+use log4rs::append::{console::{ConsoleAppender, Target}, file::FileAppender};
 use log4rs::encode::pattern::PatternEncoder;
 use log4rs::config::{Appender, Root};
 use log4rs::Config;
 use log::LevelFilter;
+use kld::error::WriteError;
 
-pub fn initialize() {
-    let _ = std::fs::remove_file("output.log");
-
+/// Logs to stderr, and to `log_file` if it's set, messages up to `level`.
+pub fn initialize(level: LevelFilter, log_file: Option<&str>) -> Result<(), WriteError> {
     //let log_format = "{d(%Y-%m-%d %H:%M:%S)} [{l}] in {f}:{L} {{{t}}}\n{m}\n";
     //let log_format = "[{l}] in {f}:{L} {{{t}}}\n{m}\n";
 
     let log_format = "{m}\n";
 
-    let stdout = ConsoleAppender::builder()
+    let stderr = ConsoleAppender::builder()
+        .target(Target::Stderr)
         .encoder(Box::new(PatternEncoder::new(log_format)))
         .build();
 
-    let log_file = FileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(log_format)))
-        .build("output.log")
-        .unwrap();
-
-    let config = Config::builder()
+    let mut config = Config::builder()
         // console appender
         .appender(Appender::builder()
-            .filter(Box::new(ThresholdFilter::new(log::LevelFilter::Trace)))
-            .build("stdout", Box::new(stdout))
-        )
-        // file appender
-        .appender(Appender::builder()
-            .build("file", Box::new(log_file))
-        )
-        // build
-        .build(Root::builder()
-                .appenders(["stdout", "file"])
-                .build(LevelFilter::Trace))
-                .unwrap();
-
-    log4rs::init_config(config).unwrap();
+            .build("stderr", Box::new(stderr))
+        );
+
+    let mut root = Root::builder().appender("stderr");
+
+    // file appender
+    if let Some(log_file) = log_file {
+        let file = FileAppender::builder()
+            .encoder(Box::new(PatternEncoder::new(log_format)))
+            .append(false)
+            .build(log_file)
+            .map_err(|source| WriteError {
+                path: log_file.to_owned(),
+                source,
+            })?;
+
+        config = config.appender(Appender::builder().build("file", Box::new(file)));
+        root = root.appender("file");
+    }
+
+    let config = config
+        .build(root.build(level))
+        .expect("invalid logging configuration");
+
+    log4rs::init_config(config).expect("logger is already initialized");
+
+    Ok(())
 }
@@ -1,273 +1,93 @@
 mod logging;
-mod reader;
-mod elf;
-mod writer;
-mod kos_application;
-
-use crate::{elf::{context::{Context, SymbolEntry}, file::{ElfObjectFile, FileParser, ElfSectionKind}}};
-use core::panic;
-use std::{collections::{VecDeque, HashMap}, path::{Path, PathBuf}};
-use elf::file::{ElfRelocation, ElfRelocationKind};
-use kos_application::KosApplication;
-
-struct Options {
-    library_paths: Vec<String>,
-    libraries: Vec<String>,
-    archives: Vec<String>,
-    objects: Vec<String>,
-    output: String,
-}
-
-fn read_options() -> Options {
-    let mut options = Options {
-        library_paths: Vec::new(),
-        libraries: Vec::new(),
-        archives: Vec::new(),
-        objects: Vec::new(),
-        output: "kos_app".to_owned(),
-    };
-
-    let mut argument_iterator = std::env::args().skip(1);
-
-    while let Some(argument) = argument_iterator.next() {
-        if argument == "-L" {
-            if let Some(library_path_argument) = argument_iterator.next() {
-                if std::path::Path::new(&library_path_argument).exists() {
-                    log::trace!("add library path: {}", library_path_argument);
-                    options.library_paths.push(library_path_argument);
-                }
-            }
-        } else if let Some(library) = argument.strip_prefix("-l") {
-            let library = library.trim();
-            log::trace!("add library: {}", library);
-            options.libraries.push(library.to_owned());
-        } else if std::path::Path::new(&argument).exists() {
-            log::trace!("detected file in argument: {}", argument);
-
-            // object
-            if argument.ends_with(".o") {
-                log::trace!("add object file");
-                options.objects.push(argument);
-            }
-            // archive
-            else if argument.ends_with(".rlib") {
-                log::trace!("add archive file");
-                options.archives.push(argument);
-            }
 
-        } else {
-            log::trace!("skip unsupported argument: {}", argument);
+use kld::elf::{context::Context, file::{ElfObjectFile, FileParser}};
+use kld::{archive, error, gc, graph, icf, map, merge, options, reader, relocation, report, reproduce, script, size, stats, trace, undefined, writer};
+use error::{Error, ParseError, ResolveError};
+use kld::kos_application::KosApplication;
+use kld::layout::{Layout, SectionMapping};
+use merge::MergedSections;
+use options::IcfMode;
+
+fn read_script(options: &options::Options) -> Result<script::Script, Error> {
+    let (script_name, script_source) = match options.script {
+        Some(ref path) => {
+            let source = std::fs::read_to_string(path)
+                .map_err(|source| error::ReadError::Io { path: path.to_owned(), source })?;
+
+            (path.as_str(), source)
         }
-    }
-
-   options
-}
-
-
-enum FoundLibraryFile {
-    NotFound,
-    Object(String),
-    Archive(String),
-}
 
-fn is_file(path: &str) -> bool {
-    let path_metadata = std::fs::metadata(path);
-
-    if path_metadata.is_err() {
-        return false;
-    }
+        None => ("<default>", script::DEFAULT_SCRIPT.to_owned()),
+    };
 
-    let path_metadata = path_metadata.unwrap();
+    let script = script::parse(&script_source)
+        .map_err(|source| ParseError::Script { path: script_name.to_owned(), source })?;
 
-    path_metadata.is_file()
+    Ok(script)
 }
 
-fn check_and_get_file_path(path: PathBuf) -> Result<String, ()> {
-    let path = path
-        .to_str()
-        .ok_or(())?;
-
-    if !is_file(path) {
-        return Err(());
-    }
+fn main() {
+    let options = options::read_options();
 
-    Ok(path.to_owned())
-}
+    // invalid options are reported with the default settings
+    let (log_level, log_file) = match options {
+        Ok(ref options) => (options.log_level, options.log_file.as_deref()),
+        Err(_) => (log::LevelFilter::Warn, None),
+    };
 
-// ! TODO: replace String to &str, make it zero copy
-fn search_libraries(
-    library_paths: &Vec<String>,
-    libraries: &Vec<String>
-) -> HashMap<String, FoundLibraryFile> {
-    let mut result = HashMap::new();
-
-    for library in libraries {
-        result.insert(library.to_owned(), FoundLibraryFile::NotFound);
-
-        for library_path in library_paths {
-            let directory_path = Path::new(library_path);
-
-            let object_file_path = directory_path
-                .join(format!("lib{}.o", library));
-
-            let archive_file_path = directory_path
-                .join(format!("lib{}.rlib", library));
-
-            if let Ok(file_path) = check_and_get_file_path(object_file_path) {
-                if let Some(found_library_file) = result.get(library) {
-                    match found_library_file {
-                        FoundLibraryFile::Object(_) | 
-                        FoundLibraryFile::Archive(_) => {
-                            log::trace!("override library: `{}`, path: {}", library, &file_path);
-                        }
-
-                        _ => {}
-                    }
-                }
-
-                result.insert(
-                    library.to_owned(), 
-                    FoundLibraryFile::Object(file_path)
-                );
-            }
-            
-            if let Ok(file_path) = check_and_get_file_path(archive_file_path) {
-                if let Some(found_library_file) = result.get(library) {
-                    match found_library_file {
-                        FoundLibraryFile::Object(_) | 
-                        FoundLibraryFile::Archive(_) => {
-                            log::trace!("override library: `{}`, path: {}", library, &file_path);
-                        }
-
-                        _ => {}
-                    }
-                }
-
-                result.insert(
-                    library.to_owned(),
-                    FoundLibraryFile::Archive(file_path)
-                );
-            }
-        }
+    if let Err(error) = logging::initialize(log_level, log_file) {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
     }
 
-    result
-}
-
-fn parse_libraries(options: &mut Options) {
-    let found_library_files = search_libraries(
-        &options.library_paths,
-        &options.libraries
-    );
+    let result = match std::env::args().nth(1).as_deref() {
+        Some("size-diff") => options.and_then(|_| size_diff()),
+        Some("replay") => options.and_then(|_| replay()),
+        _ => options.and_then(link),
+    };
 
-    for (library_name, found_library_file) in found_library_files {
-        match found_library_file {
-            FoundLibraryFile::NotFound => {
-                log::trace!("library not found: {}", library_name);
-            }
-
-            FoundLibraryFile::Object(path) => {
-                log::trace!("add library: {}, object file: {}", library_name, path);
-                options.objects.push(path);
-            }
-
-            FoundLibraryFile::Archive(path) => {
-                log::trace!("add library: {}, archive file: {}", library_name, path);
-                options.archives.push(path);
-            }
-        }
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
     }
 }
 
-fn generate_symbol_map(
-    _context: &Context,
-    code_tag_offset_map: &HashMap::<String, (usize, usize)>,
-    code_base_addr: usize,
-    data_tag_offset_map: &HashMap::<String, (usize, usize)>,
-    data_base_addr: usize,
-) {
-    use std::fmt::Write;
-    use symbolic_common::{Language, Name, NameMangling};
-    use symbolic_demangle::{Demangle, DemangleOptions};
-
-    let mut string_builder = String::new();
-
-    // code
-
-    for (tag, (offset, size)) in code_tag_offset_map.iter() {
-        let begin_address = code_base_addr + offset;
-        let end_address = begin_address + size;
-        //let mangled_names = symbol_table.get_tag_names(*tag);
-
-        writeln!(
-            &mut string_builder,
-            "[c] {begin_address:08X} - {end_address:08X} ({size}):",
-            begin_address = begin_address,
-            end_address = end_address - 1,
-            size = end_address - begin_address
-        ).expect("cannot append string");
-
-        let mangled_name = tag;
-
-        let name = Name::new(
-            mangled_name,
-            NameMangling::Mangled,
-            Language::Rust
-        );
-
-        let demangled_name = Demangle::try_demangle(
-            &name, 
-            DemangleOptions::complete()
-        );
-
-        writeln!(&mut string_builder, "- {} ({})\n", demangled_name, mangled_name)
-            .expect("cannot append string");
-    }
-
-    // data
+/// `kld size-diff OLD.json NEW.json` compares two reports written by
+/// `--report-json`.
+fn size_diff() -> Result<(), Error> {
+    let paths = std::env::args()
+        .skip(2)
+        .filter(|argument| !argument.starts_with('-'))
+        .collect::<Vec<_>>();
 
-    for (tag, (offset, size)) in data_tag_offset_map.iter() {
-        let begin_address = data_base_addr + offset;
-        let end_address = begin_address + size;
-        //let mangled_names = symbol_table.get_tag_names(*tag);
-
-        writeln!(
-            &mut string_builder,
-            "[d] {begin_address:08X} - {end_address:08X} ({size}):",
-            begin_address = begin_address,
-            end_address = end_address - 1,
-            size = end_address - begin_address
-        ).expect("cannot append string");
-
-        let mangled_name = tag;
+    let [old_path, new_path] = paths.as_slice() else {
+        return Err(Error::Options("usage: kld size-diff OLD.json NEW.json".to_owned()));
+    };
 
-        let name = Name::new(
-            mangled_name,
-            NameMangling::Mangled,
-            Language::Rust
-        );
+    let old_report = report::LinkReport::read(old_path)?;
+    let new_report = report::LinkReport::read(new_path)?;
 
-        let demangled_name = Demangle::try_demangle(
-            &name, 
-            DemangleOptions::complete()
-        );
+    print!("{}", size::generate_size_diff(&old_report, &new_report));
 
-        writeln!(&mut string_builder, "- {} ({})\n", demangled_name, mangled_name)
-            .expect("cannot append string");
-    }
-
-    std::fs::write("map.txt", string_builder)
-        .expect("cannot save the symbol map on the disk");
+    Ok(())
 }
 
+/// `kld replay REPRO.tar` links the inputs of a `--reproduce` archive.
+fn replay() -> Result<(), Error> {
+    let paths = std::env::args()
+        .skip(2)
+        .filter(|argument| !argument.starts_with('-'))
+        .collect::<Vec<_>>();
 
-fn main() {
-    logging::initialize();
+    let [path] = paths.as_slice() else {
+        return Err(Error::Options("usage: kld replay REPRO.tar".to_owned()));
+    };
 
-    std::panic::set_hook(Box::new(|panic_info| {
-        log::error!("{}", panic_info);
-    }));
+    link(reproduce::replay(path)?)
+}
 
+/// Runs the link stages, the first failed stage stops the link.
+fn link(mut options: options::Options) -> Result<(), Error> {
     let args_array_string = std::env::args()
         .skip(1)
         .map(|arg| format!("\"{}\"", arg.replace('\\', "\\\\")))
@@ -275,407 +95,207 @@ fn main() {
 
     log::trace!("\"args\": [{}]", args_array_string);
 
-    let mut options = read_options();
+    let mut timer = stats::Timer::new();
 
-    parse_libraries(&mut options);
+    options::parse_libraries(&mut options);
 
     let files = reader::read_files(
         &options.objects, 
         &options.archives
-    );
+    )?;
+
+    timer.finish("read");
+
+    if let Some(ref reproduce_path) = options.reproduce {
+        reproduce::write_reproducer(reproduce_path, &options)?;
+        timer.finish("reproduce");
+    }
 
     // new:
 
     let mut context = Context::new();
 
-    for raw_archive_file in files.archives {
-        for raw_object_file in raw_archive_file.objects {
-            let mut object_file = ElfObjectFile::new(raw_object_file.filename.to_owned());
-            object_file.parse(&raw_object_file.data, &mut context).expect("cannot parse archive file");
-            context.objects.push(object_file);
-        }
-    }
+    context.allow_multiple_definition = options.allow_multiple_definition;
 
     for raw_object_file in files.objects {
-        let mut object_file = ElfObjectFile::new(raw_object_file.filename.to_owned());
-        object_file.parse(&raw_object_file.data, &mut context).expect("cannot parse object file");
+        let mut object_file = ElfObjectFile::new(raw_object_file.filename.to_owned(), None);
+        object_file.parse(&raw_object_file.data, &mut context)?;
+
+        if options.trace {
+            println!("{}", object_file.get_display_name());
+        }
+
         context.objects.push(object_file);
     }
 
-    log::trace!("context: {:?}", context);
+    let script = read_script(&options)?;
 
-    // analyze
+    log::trace!("script: {:?}", script);
 
-    let entry_point_symbol_entry = context
-        .symbol_map
-        .get("_start")
-        .expect("entry point '_start' not found");
+    let entry = script.entry.as_deref().unwrap_or("_start");
 
-    // generate code
+    let extractions = archive::extract_members(&mut context, &files.archives, entry)?;
 
-    let mut code_buffer = Vec::<u8>::new();
-    let mut data_buffer = Vec::<u8>::new();
+    if options.trace {
+        for extraction in extractions.iter() {
+            println!("{}", extraction.extracted);
+        }
+    }
+
+    timer.finish("parse");
 
-    let mut code_tag_offset_map = HashMap::<String, (usize, usize)>::new();
-    let mut data_tag_offset_map = HashMap::<String, (usize, usize)>::new();
+    log::trace!("context: {:?}", context);
 
-    // emit code
+    for symbol_name in options.trace_symbols.iter() {
+        print!("{}", trace::trace_symbol(&context, symbol_name));
+    }
 
-    let mut queue = VecDeque::<(String, SymbolEntry)>::new();
-    queue.push_back(("_start".to_owned(), entry_point_symbol_entry.clone()));
+    if !context.duplicate_symbols.is_empty() {
+        let duplicate_symbols = std::mem::take(&mut context.duplicate_symbols);
+        return Err(ResolveError::DuplicateSymbols(duplicate_symbols).into());
+    }
 
-    while let Some((symbol_name, symbol_entry)) = queue.pop_front() {
-        if code_tag_offset_map.contains_key(&symbol_name) ||
-           data_tag_offset_map.contains_key(&symbol_name) {
-            continue;
-        }
+    // analyze
 
-        let strong_symbol = match symbol_entry {
-            SymbolEntry::Resolved(v) => v.upgrade(),
-            SymbolEntry::Unresolved => panic!("unresolved symbol"),
-        };
+    let mapping = SectionMapping::new(&context, &script);
 
-        let strong_symbol = strong_symbol.expect("cannot get symbol");
-        let strong_symbol = (*strong_symbol).borrow();
+    if !context.symbol_map.contains_key(entry) {
+        return Err(ResolveError::EntryNotFound(entry.to_owned()).into());
+    }
 
-        let symbol_section = strong_symbol.section.clone().expect("cannot get resolved symbol section");
-        let symbol_section = symbol_section.upgrade().expect("got empty weak section");
-        let symbol_section = (*symbol_section).borrow();
-        let symbol_section_data = symbol_section.data.as_ref().expect("cannot get resolved section data");
+    if let Some(ref why_extract_path) = options.why_extract {
+        let why_extract = trace::generate_why_extract(&extractions);
 
+        if why_extract_path == "-" {
+            print!("{}", why_extract);
+        } else {
+            writer::write_file_atomically(why_extract_path, why_extract.as_bytes())?;
+        }
+    }
 
-        let (buffer, offset_map, padding_byte) = match symbol_section.kind {
-            ElfSectionKind::Code => {
-                (&mut code_buffer, &mut code_tag_offset_map, 0x90)
-            }
+    let script_symbols = script.defined_symbols();
 
-            ElfSectionKind::Data => {
-                (&mut data_buffer, &mut data_tag_offset_map, 0x00)
-            }
-        };
+    let gc::LiveSections { sections: mut live_sections, reasons: live_reasons } = gc::collect_live_sections(
+        &context,
+        &mapping,
+        &script_symbols,
+        entry
+    )?;
 
-        // add alignment padding
+    for pattern in options.why_live.iter() {
+        print!("{}", gc::explain_live_symbols(&context, &live_reasons, pattern)?);
+    }
 
-        while buffer.len() % symbol_section.alignment > 0 {
-            buffer.push(padding_byte);
-        }
+    let undefined_symbols = undefined::find_undefined_symbols(
+        &context,
+        &mapping,
+        &script_symbols,
+        &live_sections
+    );
 
-        //
+    if !undefined_symbols.is_empty() {
+        return Err(ResolveError::UndefinedSymbols(undefined_symbols).into());
+    }
 
-        let offset = buffer.len();
+    timer.finish("resolve");
 
-        offset_map.insert(symbol_name.to_owned(), (offset, symbol_section_data.len()));
-        buffer.extend(symbol_section_data);
+    let mut merged_sections = MergedSections::default();
 
-        if symbol_section_data.len() < symbol_section.size {
-            for _ in 0..(symbol_section.size - symbol_section_data.len()) {
-                buffer.push(padding_byte);
-            }
-        }
+    if options.icf != IcfMode::None {
+        icf::fold_identical_sections(&context, &mut live_sections, options.icf, &mut merged_sections);
+    }
 
-        for relocation in symbol_section.relocations.iter() {
-            let relocation_symbol = match relocation.target {
-                elf::file::ElfRelocationTarget::Symbol(ref weak_symbol) => weak_symbol,
-                _ => unreachable!(),
-            };
+    merge::merge_sections(&context, &live_sections, &mut merged_sections);
 
-            let relocation_symbol1 = relocation_symbol.upgrade().expect("cannot get strong symbol");
-            let relocation_symbol2 = (*relocation_symbol1).borrow();
-            let relocation_symbol_name = relocation_symbol2.name.as_ref().expect("cannot get symbol name");
+    if options.print_gc_sections {
+        print!("{}", gc::generate_removed_sections_report(&context, &mapping, &live_reasons, &merged_sections));
+    }
 
-            let relocation_symbol_entry = context
-                .symbol_map
-                .get(relocation_symbol_name)
-                .unwrap_or_else(|| panic!("relocation target not found '{}'", relocation_symbol_name));
+    timer.finish("merge");
 
-            queue.push_back((relocation_symbol_name.to_owned(), relocation_symbol_entry.clone()));
+    // layout
 
-            //     let relocation_symbol_entry = context
-            //         .symbol_map
-            //         .get(&relocation_symbol_name)
-            //         .expect(&format!("relocation target not found '{}'", relocation_symbol_name));
-        
-            //     let (relocation_target_name, relocation_target_entry) = match relocation_symbol_entry {
-            //         SymbolEntry::Resolved(resolved_symbol) => {
-            //             let resolved_symbol = resolved_symbol.;
-            //             let resolved_symbol_entry = ;
+    let mut layout = Layout::new(
+        &context,
+        &script,
+        &mapping,
+        &live_sections,
+        merged_sections,
+        KosApplication::HEADER_SIZE
+    )?;
 
-            //             (resolved_symbol_name, resolved_symbol_entry)
-            //         }
+    timer.finish("layout");
 
-            //         SymbolEntry::Unresolved => panic!("unresolved symbol '{}'", relocation_symbol_name),
-            // };
+    layout.emit();
 
-            //queue.push_back((relocation_target_name, relocation_target_entry));
-        }
-    }
+    timer.finish("emit");
 
-    log::trace!("code_tag_offset_map: {:?}\n", code_tag_offset_map);
-    log::trace!("data_tag_offset_map: {:?}\n", data_tag_offset_map);
+    let cross_reference_table = options.cref.then(|| map::generate_cross_reference_table(&context, &layout));
 
-    // create executable
+    if let Some(ref map_path) = options.map {
+        let mut link_map = map::generate_link_map(&context, &layout, &options.output);
 
-    let mut kos_app = KosApplication::new(
-        &options.output
-    );
+        if let Some(ref cross_reference_table) = cross_reference_table {
+            link_map.push_str(cross_reference_table);
+        }
 
-    let (code_offset, data_offset) = kos_app.get_offsets(
-        code_buffer.len(), 
-        data_buffer.len()
-    );
+        writer::write_file_atomically(map_path, link_map.as_bytes())?;
+    } else if let Some(ref cross_reference_table) = cross_reference_table {
+        print!("{}", cross_reference_table);
+    }
 
-    // patch relocations
+    if let Some(ref graph_path) = options.emit_graph {
+        let reference_graph = graph::generate_reference_graph(&context, &layout);
+        writer::write_file_atomically(graph_path, reference_graph.as_bytes())?;
+    }
 
-    let code_base_addr = code_offset;
-    let data_base_addr = data_offset;
+    if options.report_json.is_some() || options.print_size_report.is_some() {
+        let link_report = report::generate_link_report(&context, &mapping, &layout, &live_reasons, &options.output);
 
-    generate_symbol_map(
-        &context,
-        &code_tag_offset_map,
-        code_base_addr,
-        &data_tag_offset_map,
-        data_base_addr
-    );
+        if let Some(ref report_path) = options.report_json {
+            writer::write_file_atomically(report_path, link_report.to_json().as_bytes())?;
+        }
 
-    // code
-    relocate(
-        &context,
-        &mut code_buffer,
-        &code_tag_offset_map,
-        &code_tag_offset_map,
-        &data_tag_offset_map,
-        code_base_addr,
-        data_base_addr,
-        ElfSectionKind::Code,
-    );
+        if let Some(grouping) = options.print_size_report {
+            print!("{}", size::generate_size_report(&link_report, grouping));
+        }
+    }
 
-    // data
-    relocate(
-        &context,
-        &mut data_buffer,
-        &data_tag_offset_map,
-        &code_tag_offset_map,
-        &data_tag_offset_map,
-        code_base_addr,
-        data_base_addr,
-        ElfSectionKind::Data,
-    );
+    timer.finish("reports");
 
-    kos_app.build(code_buffer, data_buffer, true);
+    // patch relocations
 
-    log::trace!("### END ###");
+    relocation::relocate(&context, &mut layout)?;
 
-    panic!("panic for debug purpose");
-}
+    timer.finish("relocate");
 
-fn relocate(
-    context: &Context,
-    buffer: &mut [u8],
-    offset_map: &HashMap<String, (usize, usize)>,
-    code_offset_map: &HashMap<String, (usize, usize)>,
-    data_offset_map: &HashMap<String, (usize, usize)>,
-    code_base_addr: usize,
-    data_base_addr: usize,
-    kind: ElfSectionKind
-) {
-    for (uid, (offset, _)) in offset_map.iter() {
-        let data_entry = context
-            .symbol_map
-            .get(uid)
-            .unwrap_or_else(|| panic!("undefined symbol with uid: {}", uid));
-
-        let data_symbol = match data_entry {
-            SymbolEntry::Resolved(resolved_symbol) => {
-                resolved_symbol.upgrade()
-            }
-
-            SymbolEntry::Unresolved => panic!("unresolved symbol"),
-        };
-
-        let data_symbol = data_symbol.expect("cannot get symbol");
-        let data_symbol = (*data_symbol).borrow();
-
-        let data_symbol_section = data_symbol.section
-            .as_ref()
-            .expect("cannot get resolved symbol section");
-
-        let data_symbol_section = data_symbol_section
-            .upgrade()
-            .expect("got empty weak section");
-
-        let data_symbol_section = (*data_symbol_section).borrow();
-
-        let data_symbol_section = match kind {
-            ElfSectionKind::Code => {
-                match data_symbol_section.kind {
-                    ElfSectionKind::Code => data_symbol_section,
-                    _ => unreachable!()
-                }
-            }
-            ElfSectionKind::Data => {
-                match data_symbol_section.kind {
-                    ElfSectionKind::Data => data_symbol_section,
-                    _ => unreachable!()
-                }
-            }
-        };
-
-        for relocation_entry in data_symbol_section.relocations.iter() {
-
-            let relocation_symbol = match &relocation_entry.target {
-                elf::file::ElfRelocationTarget::Symbol(weak_symbol) => weak_symbol,
-                _ => unreachable!(),
-            };
-
-            let relocation_symbol = relocation_symbol
-                .upgrade()
-                .expect("cannot get strong symbol");
-
-            let relocation_symbol = (*relocation_symbol).borrow();
-
-            let relocation_symbol_name = relocation_symbol
-                .name
-                .as_ref()
-                .expect("cannot get symbol name");
-
-            let relocation_symbol_entry = context
-                .symbol_map
-                .get(relocation_symbol_name)
-                .unwrap_or_else(|| panic!("relocation target not found '{}'", relocation_symbol_name));
-
-            let resolved_symbol = match relocation_symbol_entry {
-                SymbolEntry::Resolved(resolved_symbol) => resolved_symbol,
-                SymbolEntry::Unresolved => panic!("got unresolved symbol"),
-            };
-
-            let resolved_symbol = resolved_symbol
-                .upgrade()
-                .expect("cannot get strong symbol");
-
-            let resolved_symbol = (*resolved_symbol).borrow();
-
-            let resolved_symbol_section = resolved_symbol.section
-                .as_ref()
-                .expect("cannot get resolved symbol section");
-
-            let resolved_symbol_section = resolved_symbol_section
-                .upgrade()
-                .expect("got empty weak section");
-
-            let resolved_symbol_section = (*resolved_symbol_section).borrow();
-
-            // let relocation_data_entry = data_table
-            //     .get(&resolved_symbol.target)
-            //     .expect(&format!("undefined symbol with uid: {:?}", relocation_entry.target));
-
-            let target_offset = resolved_symbol.offset;
-
-            match resolved_symbol_section.kind {
-                ElfSectionKind::Code => {
-                    relocate2(
-                        relocation_entry,
-                        relocation_symbol_name,
-                        target_offset,
-                        *offset,
-                        buffer,
-                        code_offset_map,
-                        code_base_addr,
-                    )
-                }
-
-                ElfSectionKind::Data => {
-                    relocate2(
-                        relocation_entry,
-                        relocation_symbol_name,
-                        target_offset,
-                        *offset,
-                        buffer,
-                        data_offset_map,
-                        data_base_addr
-                    )
-                }
-            }
-        }
-    }
-}
+    // create executable
 
-fn relocate2(
-    relocation_entry: &ElfRelocation,
-    relocation_target: &str,
-    relocation_target_offset: usize,
-    offset: usize,
-    buffer: &mut [u8],
-    offset_map: &HashMap<String, (usize, usize)>,
-    base_addr: usize,
-) {
-    match relocation_entry.kind {
-        ElfRelocationKind::Absolute => {
-            let (reloc_tag_offset, _) = offset_map
-                .get(relocation_target)
-                .expect("undefined relocation symbol");
-
-            let address = base_addr + reloc_tag_offset + relocation_target_offset;
-
-            patch_abs_reloc(
-                buffer,
-                offset + relocation_entry.offset as usize,
-                relocation_entry.size, 
-                address,
-                true
-            );
-        }
+    let mut kos_app = KosApplication::new(
+        &options.output
+    );
 
-        ElfRelocationKind::Relative => {
-            let (reloc_tag_offset, _) = offset_map
-                .get(relocation_target)
-                .expect("undefined relocation symbol");
+    kos_app.build(&layout)?;
 
-            let address = base_addr + reloc_tag_offset + relocation_target_offset;
+    timer.finish("write");
 
-            patch_rel_reloc(
-                buffer,
-                offset + relocation_entry.offset as usize,
-                base_addr,
-                relocation_entry.size,
-                address
-            );               
-        }
+    if options.time_trace {
+        let time_trace_path = options.time_trace_file
+            .clone()
+            .unwrap_or_else(|| format!("{}.time-trace", options.output));
+
+        writer::write_file_atomically(&time_trace_path, timer.to_chrome_trace().as_bytes())?;
     }
-}
 
-fn patch_abs_reloc(buffer: &mut [u8], offset: usize, size: usize, value: usize, add_current_value: bool) {
-    log::trace!("[reloc_abs_patch] off: {}, size: {}, value: {:08X}", offset, size, value);
-
-    match size {
-        4 => {
-            let current_value = if add_current_value {
-                u32::from_le_bytes([
-                    buffer[offset],
-                    buffer[offset + 1],
-                    buffer[offset + 2],
-                    buffer[offset + 3],
-                ])
-            } else {
-                0
-            };
-
-            (&mut buffer[offset..(offset + size)]).copy_from_slice(
-                &((value as u32).wrapping_add(current_value)).to_le_bytes()
-            );
-        }
+    if options.stats {
+        let output_size = std::fs::metadata(&options.output)
+            .map(|metadata| metadata.len())
+            .unwrap_or_default();
 
-        _ => unreachable!()
+        print!("{}", stats::generate_stats(&timer, &context, &layout, live_sections.len(), output_size));
     }
-}
 
-fn patch_rel_reloc(buffer: &mut [u8], offset: usize, base_address: usize, size: usize, value: usize) {
-    log::trace!("[reloc_rel_patch] off: {} ({:08X}), size: {}, value: {:08X}", offset, offset, size, value);
- 
-    let relative_value = value.wrapping_sub(
-        base_address.wrapping_add(
-            offset.wrapping_add(size)
-        )
-    );
+    log::trace!("### END ###");
 
-    patch_abs_reloc(buffer, offset, size, relative_value, false);
+    Ok(())
 }
@@ -0,0 +1,232 @@
+use std::{collections::{BTreeSet, HashMap}, fmt::Write};
+use crate::elf::{context::{Context, SymbolEntry}, file::{ElfRelocationTarget, ElfSymbolType}, utils};
+use crate::layout::Layout;
+
+/// Width of the name column, longer names are followed by a line break.
+const NAME_WIDTH: usize = 16;
+
+/// Formats a link map compatible with GNU ld `-Map`.
+///
+/// Lists discarded input sections, loaded files, output sections with
+/// their input sections, alignment padding (`*fill*`) and global symbols
+/// sorted by address. Symbols defined by the script are listed between
+/// output sections.
+pub fn generate_link_map(context: &Context, layout: &Layout, output: &str) -> String {
+    let mut map = String::new();
+
+    // discarded input sections
+
+    writeln!(&mut map, "\nDiscarded input sections\n").expect("cannot append string");
+
+    for object in context.objects.iter() {
+        for section in object.sections() {
+            let section = (*section).borrow();
+
+            if section.metadata || layout.section_addresses.contains_key(&section.uid) {
+                continue;
+            }
+
+            write_entry(&mut map, &format!(" {}", section.name), 0, section.size, &section.get_file_name());
+        }
+    }
+
+    // memory
+
+    writeln!(&mut map, "\nMemory Configuration\n").expect("cannot append string");
+    writeln!(&mut map, "{:<17}{:<19}{:<19}Attributes", "Name", "Origin", "Length").expect("cannot append string");
+    writeln!(&mut map, "{:<17}{:<19}0x{:08x}", "*default*", "0x00000000", u32::MAX).expect("cannot append string");
+
+    writeln!(&mut map, "\nLinker script and memory map\n").expect("cannot append string");
+
+    let mut input_paths = Vec::<String>::new();
+
+    for object in context.objects.iter() {
+        let input_path = object.get_input_path();
+
+        if !input_paths.contains(&input_path) {
+            writeln!(&mut map, "LOAD {}", input_path).expect("cannot append string");
+            input_paths.push(input_path);
+        }
+    }
+
+    // global symbols by input sections
+
+    let mut section_symbols = layout.get_section_symbols(context);
+
+    for symbols in section_symbols.values_mut() {
+        symbols.retain(|(_, symbol_name)| !symbol_name.contains(utils::LOCAL_SYMBOL_SUFFIX));
+    }
+
+    let mut linker_symbols = layout.symbols
+        .iter()
+        .map(|(symbol_name, address)| (*address, symbol_name.as_str()))
+        .collect::<Vec<_>>();
+
+    linker_symbols.sort_unstable();
+
+    let mut linker_symbols = linker_symbols.into_iter().peekable();
+
+    // output sections
+
+    let mut output_sections = layout.output_sections.iter().collect::<Vec<_>>();
+    output_sections.sort_by_key(|output_section| output_section.address);
+
+    for output_section in output_sections {
+        while let Some((address, symbol_name)) = linker_symbols.next_if(|(address, _)| *address <= output_section.address) {
+            write_symbol(&mut map, address, symbol_name);
+        }
+
+        writeln!(&mut map).expect("cannot append string");
+        write_entry(&mut map, &output_section.name, output_section.address, output_section.size, "");
+
+        let mut position = output_section.address;
+
+        for section in output_section.input_sections.iter() {
+            let section = (*section).borrow();
+            let address = layout.get_section_address(&section);
+
+            if address > position {
+                write_entry(&mut map, " *fill*", position, address - position, "");
+            }
+
+            write_entry(&mut map, &format!(" {}", section.name), address, section.size, &section.get_file_name());
+
+            let mut symbols = section_symbols
+                .remove(&section.uid)
+                .unwrap_or_default();
+
+            symbols.sort_unstable();
+
+            for (address, symbol_name) in symbols {
+                write_symbol(&mut map, address, symbol_name);
+            }
+
+            position = address + section.size;
+        }
+
+        let end_address = output_section.address + output_section.size;
+
+        if end_address > position {
+            write_entry(&mut map, " *fill*", position, end_address - position, "");
+        }
+    }
+
+    for (address, symbol_name) in linker_symbols {
+        write_symbol(&mut map, address, symbol_name);
+    }
+
+    writeln!(&mut map, "OUTPUT({} menuet01)", output).expect("cannot append string");
+
+    map
+}
+
+/// Width of the symbol column of the cross reference table.
+const CREF_NAME_WIDTH: usize = 50;
+
+/// Formats a cross reference table compatible with GNU ld `--cref`.
+///
+/// Global symbols are sorted by demangled name, each one is followed by
+/// the defining file and then files that reference it, either through
+/// relocations or undefined symbol table entries. Symbols defined by the
+/// script and `__start_`/`__stop_` symbols are defined by `*linker*`.
+pub fn generate_cross_reference_table(context: &Context, layout: &Layout) -> String {
+    let mut references = HashMap::<&str, BTreeSet<String>>::new();
+
+    for object in context.objects.iter() {
+        let file_name = object.get_display_name();
+
+        for symbol in object.symbols() {
+            let symbol = (*symbol).borrow();
+
+            if symbol.sym_type == ElfSymbolType::External {
+                if let Some((symbol_name, _)) = symbol.name.as_deref().and_then(|name| context.symbol_map.get_key_value(name)) {
+                    references.entry(symbol_name).or_default().insert(file_name.to_owned());
+                }
+            }
+        }
+
+        for section in object.sections() {
+            for relocation in (*section).borrow().relocations.iter() {
+                let ElfRelocationTarget::Symbol(ref weak_symbol) = relocation.target else {
+                    continue;
+                };
+
+                let symbol = weak_symbol.upgrade().expect("cannot get strong symbol");
+                let symbol = (*symbol).borrow();
+
+                if let Some((symbol_name, _)) = symbol.name.as_deref().and_then(|name| context.symbol_map.get_key_value(name)) {
+                    references.entry(symbol_name).or_default().insert(file_name.to_owned());
+                }
+            }
+        }
+    }
+
+    let mut symbols = context.symbol_map
+        .iter()
+        .filter(|(symbol_name, _)| !symbol_name.contains(utils::LOCAL_SYMBOL_SUFFIX))
+        .map(|(symbol_name, symbol_entry)| (utils::demangle(symbol_name), symbol_name.as_str(), symbol_entry))
+        .collect::<Vec<_>>();
+
+    symbols.sort_unstable_by(|(a_demangled, a_name, _), (b_demangled, b_name, _)| (a_demangled, a_name).cmp(&(b_demangled, b_name)));
+
+    let mut table = String::new();
+
+    writeln!(&mut table, "\nCross Reference Table\n").expect("cannot append string");
+    writeln!(&mut table, "{:<width$}File", "Symbol", width = CREF_NAME_WIDTH).expect("cannot append string");
+
+    for (demangled_name, symbol_name, symbol_entry) in symbols {
+        let definition = match symbol_entry {
+            SymbolEntry::Resolved(weak_symbol) => {
+                let symbol = weak_symbol.upgrade().expect("cannot get symbol");
+                let file_name = (*symbol).borrow().get_file_name();
+                file_name
+            }
+
+            SymbolEntry::Unresolved { .. } if layout.symbols.contains_key(symbol_name) => "*linker*".to_owned(),
+            SymbolEntry::Unresolved { weak: true } => "*undefined weak*".to_owned(),
+            SymbolEntry::Unresolved { weak: false } => "*undefined*".to_owned(),
+        };
+
+        let files = std::iter::once(definition.to_owned())
+            .chain(
+                references
+                    .remove(symbol_name)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|file_name| *file_name != definition)
+            );
+
+        for (index, file_name) in files.enumerate() {
+            let name = if index == 0 { demangled_name.as_str() } else { "" };
+
+            if name.len() < CREF_NAME_WIDTH {
+                writeln!(&mut table, "{:<width$}{}", name, file_name, width = CREF_NAME_WIDTH).expect("cannot append string");
+            } else {
+                writeln!(&mut table, "{}\n{:<width$}{}", name, "", file_name, width = CREF_NAME_WIDTH).expect("cannot append string");
+            }
+        }
+    }
+
+    table
+}
+
+/// Writes a section line: name, address, size and file.
+fn write_entry(map: &mut String, name: &str, address: usize, size: usize, filename: &str) {
+    if name.len() < NAME_WIDTH {
+        write!(map, "{:<width$}", name, width = NAME_WIDTH).expect("cannot append string");
+    } else {
+        write!(map, "{}\n{:<width$}", name, "", width = NAME_WIDTH).expect("cannot append string");
+    }
+
+    let size = format!("0x{:x}", size);
+
+    if filename.is_empty() && !name.starts_with(' ') {
+        writeln!(map, "0x{:08x} {:>10}", address, size).expect("cannot append string");
+    } else {
+        writeln!(map, "0x{:08x} {:>10} {}", address, size, filename).expect("cannot append string");
+    }
+}
+
+fn write_symbol(map: &mut String, address: usize, name: &str) {
+    writeln!(map, "{:<width$}0x{:08x}{:<width$}{}", "", address, "", name, width = NAME_WIDTH).expect("cannot append string");
+}
@@ -0,0 +1,435 @@
+use std::{cell::RefCell, collections::{HashMap, HashSet}, ops::Range, rc::Rc};
+use object::elf;
+use crate::elf::{context::Context, file::ElfSection};
+use crate::layout::align_up;
+
+/// String or constant of a mergeable input section.
+struct Piece {
+    /// Offset in the original section.
+    input_offset: usize,
+    size: usize,
+    /// Section holding the piece after deduplication.
+    target: u64,
+    /// Offset in the target section after deduplication.
+    target_offset: usize,
+}
+
+/// Offset translation of deduplicated `SHF_MERGE` sections and sections
+/// folded by ICF.
+#[derive(Default)]
+pub struct MergedSections {
+    /// Section uid -> pieces sorted by input offset.
+    pieces: HashMap<u64, Vec<Piece>>,
+    /// Folded section uid -> uid of the identical kept section.
+    folded: HashMap<u64, u64>,
+}
+
+impl MergedSections {
+    /// Replaces the section with the identical `target` section.
+    pub fn fold(&mut self, uid: u64, target: u64) {
+        self.folded.insert(uid, target);
+    }
+
+    /// Returns uid of the section the folded section is replaced with.
+    pub fn get_folded_target(&self, uid: u64) -> Option<u64> {
+        self.folded.get(&uid).copied()
+    }
+
+    /// Translates an offset in the original input section to the section
+    /// and the offset holding the same bytes after deduplication.
+    pub fn translate(&self, uid: u64, offset: usize) -> (u64, usize) {
+        if let Some(target) = self.get_folded_target(uid) {
+            return (target, offset);
+        }
+
+        let Some(pieces) = self.pieces.get(&uid) else {
+            return (uid, offset);
+        };
+
+        let index = pieces.partition_point(|piece| piece.input_offset + piece.size <= offset);
+
+        // offsets past the end stay relative to the last piece
+        match pieces.get(index).or_else(|| pieces.last()) {
+            Some(piece) => (piece.target, piece.target_offset + offset - piece.input_offset),
+            None => (uid, offset),
+        }
+    }
+}
+
+/// Deduplicates strings and constants of live `SHF_MERGE` sections.
+///
+/// Sections with the same flags, entry size and alignment form a group.
+/// Every unique piece of a group is kept in the first section containing
+/// it, other sections drop their copies. Strings which are suffixes of
+/// other strings are tail merged. Section contents are replaced with the
+/// kept pieces, `merged_sections` receives translation of the original offsets.
+pub fn merge_sections(
+    context: &Context,
+    live_sections: &[Rc<RefCell<ElfSection>>],
+    merged_sections: &mut MergedSections,
+) {
+    let live = live_sections
+        .iter()
+        .map(|section| (*section).borrow().uid)
+        .collect::<HashSet<_>>();
+
+    let sections = context.objects
+        .iter()
+        .flat_map(|object| object.sections())
+        .filter(|section| live.contains(&(**section).borrow().uid));
+
+    for group in group_sections(sections) {
+        merge_group(&group, merged_sections);
+    }
+}
+
+/// Groups mergeable sections by flags, entry size and alignment, groups
+/// and their sections keep the input order.
+fn group_sections(sections: impl Iterator<Item = Rc<RefCell<ElfSection>>>) -> Vec<Vec<Rc<RefCell<ElfSection>>>> {
+    let mut group_indices = HashMap::<(u64, usize, usize), usize>::new();
+    let mut groups = Vec::<Vec<Rc<RefCell<ElfSection>>>>::new();
+
+    for section in sections {
+        let section_ref = (*section).borrow();
+
+        if !is_mergeable(&section_ref) {
+            continue;
+        }
+
+        let key = (section_ref.flags, section_ref.entry_size, section_ref.alignment);
+
+        let index = *group_indices
+            .entry(key)
+            .or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+
+        groups[index].push(Rc::clone(&section));
+    }
+
+    groups
+}
+
+fn is_mergeable(section: &ElfSection) -> bool {
+    section.is_alloc() &&
+    section.flags & (elf::SHF_MERGE as u64) != 0 &&
+    section.entry_size != 0 &&
+    !section.uninitialized &&
+    section.relocations.is_empty() &&
+    section.data.as_ref().is_some_and(|data| data.len() == section.size)
+}
+
+/// Splits contents into null-terminated strings or fixed-size constants.
+fn split_pieces(data: &[u8], entry_size: usize, strings: bool) -> Option<Vec<Range<usize>>> {
+    if !data.len().is_multiple_of(entry_size) {
+        return None;
+    }
+
+    if !strings {
+        return Some(
+            (0..data.len())
+                .step_by(entry_size)
+                .map(|offset| offset..(offset + entry_size))
+                .collect()
+        );
+    }
+
+    let mut pieces = Vec::new();
+    let mut start = 0;
+
+    for offset in (0..data.len()).step_by(entry_size) {
+        if data[offset..(offset + entry_size)].iter().all(|&byte| byte == 0) {
+            pieces.push(start..(offset + entry_size));
+            start = offset + entry_size;
+        }
+    }
+
+    // unterminated string
+    if start != data.len() {
+        return None;
+    }
+
+    Some(pieces)
+}
+
+fn merge_group(sections: &[Rc<RefCell<ElfSection>>], merged_sections: &mut MergedSections) {
+    let (flags, entry_size, alignment) = {
+        let section = (*sections[0]).borrow();
+        (section.flags, section.entry_size, section.alignment)
+    };
+
+    let strings = flags & (elf::SHF_STRINGS as u64) != 0;
+
+    // split sections and collect unique pieces in order of the first occurrence
+
+    let mut split_sections = Vec::new();
+    let mut unique_indices = HashMap::<Vec<u8>, usize>::new();
+    let mut unique_pieces = Vec::<(usize, Vec<u8>)>::new();
+
+    for section in sections {
+        let section_ref = (**section).borrow();
+        let data = section_ref.data.as_deref().unwrap_or_default();
+
+        let Some(pieces) = split_pieces(data, entry_size, strings) else {
+            log::trace!("section `{}` is not mergeable", section_ref.name);
+            continue;
+        };
+
+        let section_index = split_sections.len();
+
+        let pieces = pieces
+            .into_iter()
+            .map(|range| {
+                let content = &data[range.clone()];
+
+                let unique_index = *unique_indices
+                    .entry(content.to_vec())
+                    .or_insert_with(|| {
+                        unique_pieces.push((section_index, content.to_vec()));
+                        unique_pieces.len() - 1
+                    });
+
+                (range, unique_index)
+            })
+            .collect::<Vec<_>>();
+
+        split_sections.push((Rc::clone(section), pieces));
+    }
+
+    // tail merging: a string sorted right after a longer string
+    // by reversed contents is its suffix
+
+    let mut heads = (0..unique_pieces.len())
+        .map(|index| (index, 0))
+        .collect::<Vec<_>>();
+
+    if strings && alignment <= entry_size {
+        let mut order = (0..unique_pieces.len()).collect::<Vec<_>>();
+
+        order.sort_by(|&a, &b| {
+            unique_pieces[b].1.iter().rev().cmp(unique_pieces[a].1.iter().rev())
+        });
+
+        let mut head: Option<usize> = None;
+
+        for index in order {
+            let content = &unique_pieces[index].1;
+
+            match head {
+                Some(head) if unique_pieces[head].1.ends_with(content) => {
+                    heads[index] = (head, unique_pieces[head].1.len() - content.len());
+                }
+
+                _ => head = Some(index),
+            }
+        }
+    }
+
+    // place heads into sections of their first occurrence
+
+    let mut contents = vec![Vec::<u8>::new(); split_sections.len()];
+    let mut locations = vec![(0, 0); unique_pieces.len()];
+
+    for (index, (section_index, content)) in unique_pieces.iter().enumerate() {
+        if heads[index].0 != index {
+            continue;
+        }
+
+        let data = &mut contents[*section_index];
+        let offset = align_up(data.len(), alignment.max(entry_size));
+
+        data.resize(offset, 0);
+        data.extend_from_slice(content);
+
+        locations[index] = ((*split_sections[*section_index].0).borrow().uid, offset);
+    }
+
+    // translate offsets and replace contents
+
+    for ((section, pieces), data) in split_sections.into_iter().zip(contents) {
+        let mut section = (*section).borrow_mut();
+
+        let pieces = pieces
+            .into_iter()
+            .map(|(range, unique_index)| {
+                let (head, head_offset) = heads[unique_index];
+                let (target, target_offset) = locations[head];
+
+                Piece {
+                    input_offset: range.start,
+                    size: range.len(),
+                    target,
+                    target_offset: target_offset + head_offset,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        log::debug!("merge section `{}`: {} -> {} bytes", section.name, section.size, data.len());
+
+        section.size = data.len();
+        section.data = Some(data);
+
+        merged_sections.pieces.insert(section.uid, pieces);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Weak;
+    use crate::elf::file::ElfSectionKind;
+    use super::*;
+
+    const STRINGS: u64 = (elf::SHF_ALLOC | elf::SHF_MERGE | elf::SHF_STRINGS) as u64;
+    const CONSTANTS: u64 = (elf::SHF_ALLOC | elf::SHF_MERGE) as u64;
+
+    fn section(uid: u64, flags: u64, entry_size: usize, alignment: usize, data: &[u8]) -> Rc<RefCell<ElfSection>> {
+        Rc::new(RefCell::new(ElfSection {
+            uid,
+            file: Weak::new(),
+            name: format!(".rodata.{}", uid),
+            data: Some(data.to_vec()),
+            size: data.len(),
+            alignment,
+            flags,
+            entry_size,
+            uninitialized: false,
+            discarded: false,
+            address_significant: false,
+            metadata: false,
+            kind: ElfSectionKind::Data,
+            relocations: Vec::new(),
+        }))
+    }
+
+    fn merge(sections: &[Rc<RefCell<ElfSection>>]) -> MergedSections {
+        let mut merged_sections = MergedSections::default();
+
+        for group in group_sections(sections.iter().cloned()) {
+            merge_group(&group, &mut merged_sections);
+        }
+
+        merged_sections
+    }
+
+    fn data(section: &Rc<RefCell<ElfSection>>) -> Vec<u8> {
+        (**section).borrow().data.clone().unwrap()
+    }
+
+    #[test]
+    fn strings_are_deduplicated_and_tail_merged() {
+        let sections = [
+            section(1, STRINGS, 1, 1, b"foobar\0"),
+            section(2, STRINGS, 1, 1, b"bar\0baz\0foobar\0"),
+        ];
+
+        let merged_sections = merge(&sections);
+
+        assert_eq!(data(&sections[0]), b"foobar\0");
+        assert_eq!(data(&sections[1]), b"baz\0");
+        assert_eq!((*sections[1]).borrow().size, 4);
+
+        // "bar\0" is the tail of "foobar\0"
+        assert_eq!(merged_sections.translate(2, 0), (1, 3));
+        assert_eq!(merged_sections.translate(2, 4), (2, 0));
+        assert_eq!(merged_sections.translate(2, 8), (1, 0));
+        assert_eq!(merged_sections.translate(1, 0), (1, 0));
+    }
+
+    #[test]
+    fn offsets_inside_pieces_are_translated() {
+        let sections = [
+            section(1, STRINGS, 1, 1, b"foobar\0"),
+            section(2, STRINGS, 1, 1, b"baz\0bar\0"),
+        ];
+
+        let merged_sections = merge(&sections);
+
+        assert_eq!(merged_sections.translate(2, 2), (2, 2));
+        assert_eq!(merged_sections.translate(2, 5), (1, 4));
+        assert_eq!(merged_sections.translate(2, 7), (1, 6));
+        // past the end stays relative to the last piece
+        assert_eq!(merged_sections.translate(2, 8), (1, 7));
+        assert_eq!(merged_sections.translate(1, 5), (1, 5));
+    }
+
+    #[test]
+    fn constants_with_entry_size_are_deduplicated() {
+        let sections = [
+            section(1, CONSTANTS, 4, 4, &[1, 0, 0, 0, 2, 0, 0, 0]),
+            section(2, CONSTANTS, 4, 4, &[2, 0, 0, 0, 3, 0, 0, 0]),
+        ];
+
+        let merged_sections = merge(&sections);
+
+        assert_eq!(data(&sections[0]), [1, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(data(&sections[1]), [3, 0, 0, 0]);
+
+        assert_eq!(merged_sections.translate(2, 0), (1, 4));
+        assert_eq!(merged_sections.translate(2, 2), (1, 6));
+        assert_eq!(merged_sections.translate(2, 4), (2, 0));
+    }
+
+    #[test]
+    fn wide_strings_are_split_by_entry_size() {
+        let sections = [
+            // "xy" and "y" in UTF-16, a zero byte of a character doesn't end the string
+            section(1, STRINGS, 2, 2, b"x\0y\0\0\0"),
+            section(2, STRINGS, 2, 2, b"y\0\0\0"),
+        ];
+
+        let merged_sections = merge(&sections);
+
+        assert_eq!(data(&sections[0]), b"x\0y\0\0\0");
+        assert_eq!(data(&sections[1]), b"");
+        assert_eq!(merged_sections.translate(2, 0), (1, 2));
+    }
+
+    #[test]
+    fn sections_are_grouped_by_flags_entry_size_and_alignment() {
+        let sections = [
+            section(1, STRINGS, 1, 1, b"a\0"),
+            section(2, STRINGS, 1, 4, b"a\0"),
+            section(3, CONSTANTS, 1, 1, b"a\0"),
+            section(4, STRINGS, 2, 2, b"a\0\0\0"),
+            section(5, STRINGS, 1, 1, b"b\0"),
+            // not mergeable
+            section(6, elf::SHF_ALLOC as u64, 1, 1, b"a\0"),
+        ];
+
+        let groups = group_sections(sections.iter().cloned())
+            .into_iter()
+            .map(|group| group.iter().map(|section| (**section).borrow().uid).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        assert_eq!(groups, [vec![1, 5], vec![2], vec![3], vec![4]]);
+    }
+
+    #[test]
+    fn strings_of_different_alignment_are_not_merged() {
+        let sections = [
+            section(1, STRINGS, 1, 1, b"foobar\0"),
+            section(2, STRINGS, 1, 4, b"foobar\0bar\0"),
+        ];
+
+        let merged_sections = merge(&sections);
+
+        assert_eq!(data(&sections[0]), b"foobar\0");
+        // over-aligned strings are neither shared with other groups nor tail merged
+        assert_eq!(data(&sections[1]), b"foobar\0\0bar\0");
+        assert_eq!(merged_sections.translate(2, 0), (2, 0));
+        assert_eq!(merged_sections.translate(2, 7), (2, 8));
+    }
+
+    #[test]
+    fn folded_sections_keep_offsets() {
+        let mut merged_sections = MergedSections::default();
+
+        merged_sections.fold(2, 1);
+
+        assert_eq!(merged_sections.get_folded_target(2), Some(1));
+        assert_eq!(merged_sections.get_folded_target(1), None);
+        assert_eq!(merged_sections.translate(2, 12), (1, 12));
+        assert_eq!(merged_sections.translate(3, 12), (3, 12));
+    }
+}
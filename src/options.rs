@@ -0,0 +1,457 @@
+use std::{collections::HashMap, path::{Path, PathBuf}};
+use log::LevelFilter;
+use crate::error::Error;
+use crate::size::SizeGrouping;
+
+/// Environment variable overriding the log level set by options:
+/// `off`, `error`, `warn`, `info`, `debug` or `trace`.
+pub const LOG_LEVEL_VARIABLE: &str = "KLD_LOG";
+
+#[derive(Clone)]
+pub struct Options {
+    pub library_paths: Vec<String>,
+    pub libraries: Vec<String>,
+    /// Libraries and the files found by `parse_libraries` sorted by name.
+    pub found_libraries: Vec<(String, Option<String>)>,
+    pub archives: Vec<String>,
+    pub objects: Vec<String>,
+    pub output: String,
+    /// Linker script path set by `-T`.
+    pub script: Option<String>,
+    /// Link map path set by `-Map`.
+    pub map: Option<String>,
+    /// Append a cross reference table to the link map, or print it
+    /// without `-Map`.
+    pub cref: bool,
+    /// JSON link report path set by `--report-json`.
+    pub report_json: Option<String>,
+    /// Reference graph path set by `--emit-graph`.
+    pub emit_graph: Option<String>,
+    /// Size report grouping set by `--print-size-report`.
+    pub print_size_report: Option<SizeGrouping>,
+    /// List sections removed by the collection, `--print-gc-sections`.
+    pub print_gc_sections: bool,
+    /// Write phase durations in the Chrome trace event format,
+    /// `--time-trace`.
+    pub time_trace: bool,
+    /// Time trace path set by `--time-trace-file`, `OUTPUT.time-trace`
+    /// by default.
+    pub time_trace_file: Option<String>,
+    /// Print phase durations and counts of inputs, `--stats`.
+    pub stats: bool,
+    /// Symbol names or regular expressions set by `--why-live`.
+    pub why_live: Vec<String>,
+    /// Symbols traced by `--trace-symbol` (`-y`).
+    pub trace_symbols: Vec<String>,
+    /// Print loaded input files and extracted archive members,
+    /// `--trace` (`-t`).
+    pub trace: bool,
+    /// Archive member extraction report path set by `--why-extract`,
+    /// `-` is stdout.
+    pub why_extract: Option<String>,
+    pub icf: IcfMode,
+    /// Keep the first definition of duplicate symbols.
+    pub allow_multiple_definition: bool,
+    /// Warnings and errors by default, `-v` adds debug messages, `-vv`
+    /// traces everything, `--quiet` leaves errors only.
+    pub log_level: LevelFilter,
+    /// Log file set by `--log-file`.
+    pub log_file: Option<String>,
+    /// Reproducer archive path set by `--reproduce`.
+    pub reproduce: Option<String>,
+}
+
+/// Identical code folding mode set by `--icf=none|safe|all`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IcfMode {
+    None,
+    /// Sections with significant addresses (`.llvm_addrsig`) are not folded.
+    Safe,
+    All,
+}
+
+pub fn read_options() -> Result<Options, Error> {
+    parse_options(std::env::args().skip(1))
+}
+
+/// Parses command line arguments without the program name.
+pub fn parse_options(arguments: impl Iterator<Item = String>) -> Result<Options, Error> {
+    let mut options = Options {
+        library_paths: Vec::new(),
+        libraries: Vec::new(),
+        found_libraries: Vec::new(),
+        archives: Vec::new(),
+        objects: Vec::new(),
+        output: "kos_app".to_owned(),
+        script: None,
+        map: None,
+        cref: false,
+        report_json: None,
+        emit_graph: None,
+        print_size_report: None,
+        print_gc_sections: false,
+        time_trace: false,
+        time_trace_file: None,
+        stats: false,
+        why_live: Vec::new(),
+        trace_symbols: Vec::new(),
+        trace: false,
+        why_extract: None,
+        icf: IcfMode::None,
+        allow_multiple_definition: false,
+        log_level: LevelFilter::Warn,
+        log_file: None,
+        reproduce: None,
+    };
+
+    let mut verbosity = 0;
+    let mut quiet = false;
+
+    let mut argument_iterator = arguments;
+
+    while let Some(argument) = argument_iterator.next() {
+        if argument == "-L" {
+            if let Some(library_path_argument) = argument_iterator.next() {
+                if std::path::Path::new(&library_path_argument).exists() {
+                    log::trace!("add library path: {}", library_path_argument);
+                    options.library_paths.push(library_path_argument);
+                }
+            }
+        } else if argument == "-T" {
+            if let Some(script_argument) = argument_iterator.next() {
+                log::trace!("set linker script: {}", script_argument);
+                options.script = Some(script_argument);
+            }
+        } else if let Some(script) = argument.strip_prefix("-T") {
+            log::trace!("set linker script: {}", script);
+            options.script = Some(script.to_owned());
+        } else if argument == "-o" || argument == "--output" {
+            if let Some(output_argument) = argument_iterator.next() {
+                log::trace!("set output: {}", output_argument);
+                options.output = output_argument;
+            }
+        } else if let Some(output) = argument.strip_prefix("--output=") {
+            log::trace!("set output: {}", output);
+            options.output = output.to_owned();
+        } else if argument == "-Map" || argument == "--Map" {
+            if let Some(map_argument) = argument_iterator.next() {
+                log::trace!("set link map: {}", map_argument);
+                options.map = Some(map_argument);
+            }
+        } else if let Some(map) = argument.strip_prefix("-Map=").or_else(|| argument.strip_prefix("--Map=")) {
+            log::trace!("set link map: {}", map);
+            options.map = Some(map.to_owned());
+        } else if argument == "--cref" {
+            options.cref = true;
+        } else if argument == "--report-json" {
+            if let Some(report_argument) = argument_iterator.next() {
+                log::trace!("set JSON link report: {}", report_argument);
+                options.report_json = Some(report_argument);
+            }
+        } else if let Some(report) = argument.strip_prefix("--report-json=") {
+            log::trace!("set JSON link report: {}", report);
+            options.report_json = Some(report.to_owned());
+        } else if argument == "--emit-graph" {
+            if let Some(graph_argument) = argument_iterator.next() {
+                log::trace!("set reference graph: {}", graph_argument);
+                options.emit_graph = Some(graph_argument);
+            }
+        } else if let Some(graph) = argument.strip_prefix("--emit-graph=") {
+            log::trace!("set reference graph: {}", graph);
+            options.emit_graph = Some(graph.to_owned());
+        } else if argument == "--print-size-report" {
+            options.print_size_report = Some(SizeGrouping::Crate);
+        } else if let Some(grouping) = argument.strip_prefix("--print-size-report=") {
+            options.print_size_report = match grouping {
+                "crate" => Some(SizeGrouping::Crate),
+                "archive" => Some(SizeGrouping::Archive),
+                _ => return Err(Error::Options(format!("unknown size report grouping: {}", grouping))),
+            };
+        } else if argument == "--print-gc-sections" {
+            options.print_gc_sections = true;
+        } else if argument == "--time-trace" {
+            options.time_trace = true;
+        } else if argument == "--time-trace-file" {
+            if let Some(time_trace_argument) = argument_iterator.next() {
+                options.time_trace = true;
+                options.time_trace_file = Some(time_trace_argument);
+            }
+        } else if let Some(time_trace) = argument.strip_prefix("--time-trace-file=") {
+            options.time_trace = true;
+            options.time_trace_file = Some(time_trace.to_owned());
+        } else if argument == "--stats" {
+            options.stats = true;
+        } else if argument == "--why-live" {
+            if let Some(pattern_argument) = argument_iterator.next() {
+                options.why_live.push(pattern_argument);
+            }
+        } else if let Some(pattern) = argument.strip_prefix("--why-live=") {
+            options.why_live.push(pattern.to_owned());
+        } else if argument == "--trace-symbol" || argument == "-y" {
+            if let Some(symbol_argument) = argument_iterator.next() {
+                options.trace_symbols.push(symbol_argument);
+            }
+        } else if let Some(symbol) = argument.strip_prefix("--trace-symbol=") {
+            options.trace_symbols.push(symbol.to_owned());
+        } else if argument == "--trace" || argument == "-t" {
+            options.trace = true;
+        } else if let Some(why_extract) = argument.strip_prefix("--why-extract=") {
+            log::trace!("set extraction report: {}", why_extract);
+            options.why_extract = Some(why_extract.to_owned());
+        } else if argument == "--reproduce" {
+            if let Some(reproduce_argument) = argument_iterator.next() {
+                log::trace!("set reproducer: {}", reproduce_argument);
+                options.reproduce = Some(reproduce_argument);
+            }
+        } else if let Some(reproduce) = argument.strip_prefix("--reproduce=") {
+            log::trace!("set reproducer: {}", reproduce);
+            options.reproduce = Some(reproduce.to_owned());
+        } else if argument == "-v" || argument == "--verbose" {
+            verbosity += 1;
+        } else if argument == "-vv" {
+            verbosity += 2;
+        } else if argument == "--quiet" {
+            quiet = true;
+        } else if argument == "--log-file" {
+            if let Some(log_file_argument) = argument_iterator.next() {
+                options.log_file = Some(log_file_argument);
+            }
+        } else if let Some(log_file) = argument.strip_prefix("--log-file=") {
+            options.log_file = Some(log_file.to_owned());
+        } else if argument == "--allow-multiple-definition" {
+            log::trace!("allow multiple definitions");
+            options.allow_multiple_definition = true;
+        } else if let Some(mode) = argument.strip_prefix("--icf=") {
+            options.icf = match mode {
+                "none" => IcfMode::None,
+                "safe" => IcfMode::Safe,
+                "all" => IcfMode::All,
+                _ => return Err(Error::Options(format!("unknown ICF mode: {}", mode))),
+            };
+
+            log::trace!("set ICF mode: {:?}", options.icf);
+        } else if let Some(library) = argument.strip_prefix("-l") {
+            let library = library.trim();
+            log::trace!("add library: {}", library);
+            options.libraries.push(library.to_owned());
+        } else if std::path::Path::new(&argument).exists() {
+            log::trace!("detected file in argument: {}", argument);
+
+            // object
+            if argument.ends_with(".o") {
+                log::trace!("add object file");
+                options.objects.push(argument);
+            }
+            // archive
+            else if argument.ends_with(".rlib") || argument.ends_with(".a") {
+                log::trace!("add archive file");
+                options.archives.push(argument);
+            }
+
+        } else {
+            log::trace!("skip unsupported argument: {}", argument);
+        }
+    }
+
+    options.log_level = match (quiet, verbosity) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Warn,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+
+    if let Ok(log_level) = std::env::var(LOG_LEVEL_VARIABLE) {
+        options.log_level = log_level
+            .parse()
+            .map_err(|_| Error::Options(format!("invalid {} value: {}", LOG_LEVEL_VARIABLE, log_level)))?;
+    }
+
+    Ok(options)
+}
+
+impl Options {
+    /// Returns the command line of the options. Libraries are passed as
+    /// the files found for them, logging and `--reproduce` are omitted.
+    pub fn to_arguments(&self) -> Vec<String> {
+        let mut arguments = Vec::new();
+
+        arguments.extend(self.objects.iter().cloned());
+        arguments.extend(self.archives.iter().cloned());
+
+        if let Some(ref script) = self.script {
+            arguments.extend(["-T".to_owned(), script.to_owned()]);
+        }
+
+        arguments.extend(["-o".to_owned(), self.output.to_owned()]);
+
+        if let Some(ref map) = self.map {
+            arguments.push(format!("-Map={}", map));
+        }
+
+        if self.cref {
+            arguments.push("--cref".to_owned());
+        }
+
+        if let Some(ref report_json) = self.report_json {
+            arguments.push(format!("--report-json={}", report_json));
+        }
+
+        if let Some(ref emit_graph) = self.emit_graph {
+            arguments.push(format!("--emit-graph={}", emit_graph));
+        }
+
+        match self.print_size_report {
+            Some(SizeGrouping::Crate) => arguments.push("--print-size-report=crate".to_owned()),
+            Some(SizeGrouping::Archive) => arguments.push("--print-size-report=archive".to_owned()),
+            None => {}
+        }
+
+        if self.print_gc_sections {
+            arguments.push("--print-gc-sections".to_owned());
+        }
+
+        match self.time_trace_file {
+            Some(ref time_trace_file) => arguments.push(format!("--time-trace-file={}", time_trace_file)),
+            None if self.time_trace => arguments.push("--time-trace".to_owned()),
+            None => {}
+        }
+
+        if self.stats {
+            arguments.push("--stats".to_owned());
+        }
+
+        arguments.extend(self.why_live.iter().map(|pattern| format!("--why-live={}", pattern)));
+        arguments.extend(self.trace_symbols.iter().map(|symbol| format!("--trace-symbol={}", symbol)));
+
+        if self.trace {
+            arguments.push("--trace".to_owned());
+        }
+
+        if let Some(ref why_extract) = self.why_extract {
+            arguments.push(format!("--why-extract={}", why_extract));
+        }
+
+        match self.icf {
+            IcfMode::None => {}
+            IcfMode::Safe => arguments.push("--icf=safe".to_owned()),
+            IcfMode::All => arguments.push("--icf=all".to_owned()),
+        }
+
+        if self.allow_multiple_definition {
+            arguments.push("--allow-multiple-definition".to_owned());
+        }
+
+        arguments
+    }
+}
+
+
+enum FoundLibraryFile {
+    NotFound,
+    Object(String),
+    Archive(String),
+}
+
+fn is_file(path: &str) -> bool {
+    let path_metadata = std::fs::metadata(path);
+
+    if path_metadata.is_err() {
+        return false;
+    }
+
+    let path_metadata = path_metadata.unwrap();
+
+    path_metadata.is_file()
+}
+
+fn check_and_get_file_path(path: PathBuf) -> Result<String, ()> {
+    let path = path
+        .to_str()
+        .ok_or(())?;
+
+    if !is_file(path) {
+        return Err(());
+    }
+
+    Ok(path.to_owned())
+}
+
+// ! TODO: replace String to &str, make it zero copy
+fn search_libraries(
+    library_paths: &Vec<String>,
+    libraries: &Vec<String>
+) -> HashMap<String, FoundLibraryFile> {
+    let mut result = HashMap::new();
+
+    for library in libraries {
+        result.insert(library.to_owned(), FoundLibraryFile::NotFound);
+
+        for library_path in library_paths {
+            let directory_path = Path::new(library_path);
+
+            let object_file_path = directory_path
+                .join(format!("lib{}.o", library));
+
+            let archive_file_paths = ["a", "rlib"]
+                .map(|extension| directory_path.join(format!("lib{}.{}", library, extension)));
+
+            if let Ok(file_path) = check_and_get_file_path(object_file_path) {
+                if let Some(FoundLibraryFile::Object(_) | FoundLibraryFile::Archive(_)) = result.get(library) {
+                    log::trace!("override library: `{}`, path: {}", library, &file_path);
+                }
+
+                result.insert(
+                    library.to_owned(), 
+                    FoundLibraryFile::Object(file_path)
+                );
+            }
+            
+            for archive_file_path in archive_file_paths {
+                if let Ok(file_path) = check_and_get_file_path(archive_file_path) {
+                    if let Some(FoundLibraryFile::Object(_) | FoundLibraryFile::Archive(_)) = result.get(library) {
+                        log::trace!("override library: `{}`, path: {}", library, &file_path);
+                    }
+
+                    result.insert(
+                        library.to_owned(),
+                        FoundLibraryFile::Archive(file_path)
+                    );
+                }
+            }
+        }
+    }
+
+    result
+}
+
+pub fn parse_libraries(options: &mut Options) {
+    let found_library_files = search_libraries(
+        &options.library_paths,
+        &options.libraries
+    );
+
+    for (library_name, found_library_file) in found_library_files {
+        let path = match found_library_file {
+            FoundLibraryFile::NotFound => {
+                log::trace!("library not found: {}", library_name);
+                None
+            }
+
+            FoundLibraryFile::Object(path) => {
+                log::trace!("add library: {}, object file: {}", library_name, path);
+                options.objects.push(path.to_owned());
+                Some(path)
+            }
+
+            FoundLibraryFile::Archive(path) => {
+                log::trace!("add library: {}, archive file: {}", library_name, path);
+                options.archives.push(path.to_owned());
+                Some(path)
+            }
+        };
+
+        options.found_libraries.push((library_name, path));
+    }
+
+    options.found_libraries.sort_unstable();
+}
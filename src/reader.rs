@@ -1,5 +1,6 @@
-use std::io::{Error, Read};
+use std::io::Read;
 use ar::Archive;
+use crate::error::ReadError;
 
 pub struct RawObjectFile {
     pub filename: String,
@@ -19,32 +20,29 @@ pub struct Files {
 pub fn read_files(
     object_files: &Vec<String>,
     archive_files: &Vec<String>
-) -> Files {
+) -> Result<Files, ReadError> {
     let mut objects = Vec::new();
     let mut archives = Vec::new();
 
     for object_file in object_files {
-        let result = read_object(object_file)
-            .unwrap_or_else(|_| panic!("cannot read object file: {}", object_file));
-
-        objects.push(result);
+        objects.push(read_object(object_file)?);
     }
 
     for archive_file in archive_files {
-        let result = read_archive(archive_file)
-            .unwrap_or_else(|_| panic!("cannot read object fle: {}", archive_file));
-
-        archives.push(result);
+        archives.push(read_archive(archive_file)?);
     }
 
-    Files {
-        objects,
-        archives,
-    }
+    Ok(
+        Files {
+            objects,
+            archives,
+        }
+    )
 }
 
-pub fn read_object(filename: &str) -> Result<RawObjectFile, Error> {
-    let data = std::fs::read(filename)?;
+pub fn read_object(filename: &str) -> Result<RawObjectFile, ReadError> {
+    let data = std::fs::read(filename)
+        .map_err(|source| ReadError::Io { path: filename.to_owned(), source })?;
 
     Ok(
         RawObjectFile {
@@ -54,26 +52,33 @@ pub fn read_object(filename: &str) -> Result<RawObjectFile, Error> {
     )
 }
 
-pub fn read_archive(filename: &str) -> Result<RawArchiveFile, Error> {
+pub fn read_archive(filename: &str) -> Result<RawArchiveFile, ReadError> {
     let mut objects = Vec::new();
 
-    let file = std::fs::File::open(filename)?;
+    let file = std::fs::File::open(filename)
+        .map_err(|source| ReadError::Io { path: filename.to_owned(), source })?;
 
     let mut archive = Archive::new(file);
 
+    let archive_error = |message: String| ReadError::Archive {
+        path: filename.to_owned(),
+        message,
+    };
+
     while let Some(entry_result) = archive.next_entry() {
-        let mut entry = entry_result.unwrap();
+        let mut entry = entry_result
+            .map_err(|error| archive_error(error.to_string()))?;
 
         let entry_file_name = 
             std::str::from_utf8(entry.header().identifier())
-            .unwrap()
+            .map_err(|_| archive_error("member name is not valid UTF-8".to_owned()))?
             .to_string();
 
         if entry_file_name.ends_with(".o") {
             let mut data = Vec::new();
 
             entry.read_to_end(&mut data)
-                .expect("cannot read an archive entry");
+                .map_err(|error| archive_error(format!("cannot read member `{}`: {}", entry_file_name, error)))?;
 
             objects.push(RawObjectFile {
                 filename: entry_file_name,
@@ -0,0 +1,295 @@
+use crate::elf::{context::Context, file::{ElfRelocationKind, ElfRelocationTarget}};
+use crate::error::RelocateError;
+use crate::layout::Layout;
+
+/// Applies relocations of all placed input sections.
+pub fn relocate(context: &Context, layout: &mut Layout) -> Result<(), RelocateError> {
+    for index in 0..layout.output_sections.len() {
+        if layout.output_sections[index].nobits {
+            continue;
+        }
+
+        let mut data = std::mem::take(&mut layout.output_sections[index].data);
+
+        let result = relocate_output_section(context, layout, index, &mut data);
+
+        layout.output_sections[index].data = data;
+
+        result?;
+    }
+
+    Ok(())
+}
+
+fn relocate_output_section(context: &Context, layout: &Layout, index: usize, buffer: &mut [u8]) -> Result<(), RelocateError> {
+    let output_section = &layout.output_sections[index];
+    let base_addr = output_section.address;
+
+    for section in output_section.input_sections.iter() {
+        let section = (*section).borrow();
+
+        let offset = layout.get_section_address(&section) - base_addr;
+
+        for relocation_entry in section.relocations.iter() {
+            if relocation_entry.size != 4 {
+                return Err(RelocateError::UnsupportedSize {
+                    size: relocation_entry.size,
+                    file: section.get_file_name(),
+                    section: section.name.to_owned(),
+                    offset: relocation_entry.offset,
+                });
+            }
+
+            let place_offset = offset + relocation_entry.offset;
+
+            let place = place_offset
+                .checked_add(relocation_entry.size)
+                .and_then(|place_end| buffer.get_mut(place_offset..place_end))
+                .and_then(|place| <&mut [u8; 4]>::try_from(place).ok())
+                .ok_or_else(|| RelocateError::OutOfBounds {
+                    file: section.get_file_name(),
+                    section: section.name.to_owned(),
+                    offset: relocation_entry.offset,
+                })?;
+
+            // the implicit addend is an offset in the target, it changes
+            // when the target section is deduplicated. A PC-relative
+            // addend is relative to the end of the place, so the target
+            // offset is `A + 4` like in lld
+            let addend = match relocation_entry.kind {
+                ElfRelocationKind::Absolute => take_implicit_addend(place),
+                ElfRelocationKind::Relative => take_implicit_addend(place).wrapping_add(place.len()) as u32 as usize,
+            };
+
+            let address = match &relocation_entry.target {
+                ElfRelocationTarget::Symbol(weak_symbol) => {
+                    let relocation_symbol = weak_symbol
+                        .upgrade()
+                        .expect("cannot get strong symbol");
+
+                    let relocation_symbol = (*relocation_symbol).borrow();
+
+                    let relocation_symbol_name = relocation_symbol
+                        .name
+                        .as_ref()
+                        .expect("cannot get symbol name");
+
+                    layout
+                        .get_symbol_offset_address(context, relocation_symbol_name, addend)
+                        .ok_or_else(|| RelocateError::UnresolvedTarget {
+                            target: relocation_symbol_name.to_owned(),
+                            file: section.get_file_name(),
+                            section: section.name.to_owned(),
+                        })?
+                }
+
+                ElfRelocationTarget::Section(weak_section) => {
+                    let relocation_section = weak_section
+                        .upgrade()
+                        .expect("got empty weak section");
+
+                    let relocation_section = (*relocation_section).borrow();
+
+                    layout
+                        .get_section_offset_address(&relocation_section, addend)
+                        .ok_or_else(|| RelocateError::UnresolvedTarget {
+                            target: relocation_section.name.to_owned(),
+                            file: section.get_file_name(),
+                            section: section.name.to_owned(),
+                        })?
+                }
+
+                ElfRelocationTarget::Absolute => addend,
+            };
+
+            match relocation_entry.kind {
+                ElfRelocationKind::Absolute => {
+                    patch_abs_reloc(place, place_offset, address, true);
+                }
+
+                ElfRelocationKind::Relative => {
+                    patch_rel_reloc(place, place_offset, base_addr, address);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the addend stored at the relocation place and clears it.
+fn take_implicit_addend(place: &mut [u8; 4]) -> usize {
+    let addend = u32::from_le_bytes(*place);
+
+    place.fill(0);
+
+    addend as usize
+}
+
+fn patch_abs_reloc(place: &mut [u8; 4], offset: usize, value: usize, add_current_value: bool) {
+    log::trace!("[reloc_abs_patch] off: {}, size: {}, value: {:08X}", offset, place.len(), value);
+
+    let current_value = if add_current_value {
+        u32::from_le_bytes(*place)
+    } else {
+        0
+    };
+
+    // addresses fit the 32-bit image, values of negative addends and
+    // relative relocations wrap around like i386 arithmetic
+    *place = (value as u32).wrapping_add(current_value).to_le_bytes();
+}
+
+fn patch_rel_reloc(place: &mut [u8; 4], offset: usize, base_address: usize, value: usize) {
+    log::trace!("[reloc_rel_patch] off: {} ({:08X}), size: {}, value: {:08X}", offset, offset, place.len(), value);
+ 
+    let relative_value = value.wrapping_sub(
+        base_address.wrapping_add(
+            offset.wrapping_add(place.len())
+        )
+    );
+
+    patch_abs_reloc(place, offset, relative_value, false);
+}
+
+#[cfg(test)]
+mod tests {
+    use object::{write, Architecture, BinaryFormat, Endianness, RelocationEncoding, RelocationKind, SectionKind, SymbolFlags, SymbolKind, SymbolScope};
+    use crate::elf::file::{ElfObjectFile, FileParser};
+    use crate::gc;
+    use crate::layout::{OutputSection, SectionMapping};
+    use crate::merge::{self, MergedSections};
+    use crate::script;
+    use super::*;
+
+    const SCRIPT: &str = "ENTRY(_start) SECTIONS { . = 0x1000; .text : { *(.text*) } .rodata : { *(.rodata*) } }";
+
+    /// Builds an i386 object with `.text` calling the targets, `_start` is
+    /// the start of `.text`. Every target is a section and an offset in it.
+    fn build_object(sections: &[(&str, SectionKind, &[u8])], calls: &[(&str, i64)]) -> Vec<u8> {
+        let mut object = write::Object::new(BinaryFormat::Elf, Architecture::I386, Endianness::Little);
+
+        let mut code = calls.iter().flat_map(|_| [0xE8, 0, 0, 0, 0]).collect::<Vec<_>>();
+        code.push(0xC3);
+
+        let text = object.add_section(Vec::new(), b".text".to_vec(), SectionKind::Text);
+        object.append_section_data(text, &code, 1);
+
+        object.add_symbol(write::Symbol {
+            name: b"_start".to_vec(),
+            value: 0,
+            size: code.len() as u64,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Linkage,
+            weak: false,
+            section: write::SymbolSection::Section(text),
+            flags: SymbolFlags::None,
+        });
+
+        let sections = sections
+            .iter()
+            .map(|&(name, kind, data)| {
+                let section = object.add_section(Vec::new(), name.as_bytes().to_vec(), kind);
+                object.append_section_data(section, data, 1);
+
+                (name, section)
+            })
+            .collect::<Vec<_>>();
+
+        for (index, &(target, offset)) in calls.iter().enumerate() {
+            let section = sections.iter().find(|(name, _)| *name == target).unwrap().1;
+            let symbol = object.section_symbol(section);
+
+            object.add_relocation(text, write::Relocation {
+                offset: (index * 5 + 1) as u64,
+                size: 32,
+                kind: RelocationKind::Relative,
+                encoding: RelocationEncoding::Generic,
+                symbol,
+                addend: offset - 4,
+            }).unwrap();
+        }
+
+        object.write().unwrap()
+    }
+
+    /// Links the object by `SCRIPT` and returns the relocated layout.
+    fn link(data: &[u8]) -> Layout {
+        let mut context = Context::new();
+        let mut object_file = ElfObjectFile::new("test.o".to_owned(), None);
+
+        object_file.parse(data, &mut context).unwrap();
+        context.objects.push(object_file);
+
+        let script = script::parse(SCRIPT).unwrap();
+        let mapping = SectionMapping::new(&context, &script);
+        let live_sections = gc::collect_live_sections(&context, &mapping, &script.defined_symbols(), "_start")
+            .unwrap()
+            .sections;
+
+        let mut merged_sections = MergedSections::default();
+        merge::merge_sections(&context, &live_sections, &mut merged_sections);
+
+        let mut layout = Layout::new(&context, &script, &mapping, &live_sections, merged_sections, 0).unwrap();
+
+        layout.emit();
+        relocate(&context, &mut layout).unwrap();
+
+        layout
+    }
+
+    fn get_output_section<'a>(layout: &'a Layout, name: &str) -> &'a OutputSection {
+        layout.output_sections.iter().find(|output_section| output_section.name == name).unwrap()
+    }
+
+    /// Returns displacements of the calls at the start of `.text`.
+    fn get_displacements(layout: &Layout, count: usize) -> Vec<u32> {
+        let text = get_output_section(layout, ".text");
+
+        (0..count)
+            .map(|index| u32::from_le_bytes(text.data[index * 5 + 1..index * 5 + 5].try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn relative_relocation_keeps_the_offset_in_the_target_section() {
+        // call .Ltarget, the label is 4 bytes into .text.other
+        let data = build_object(
+            &[(".text.other", SectionKind::Text, &[0x90, 0x90, 0x90, 0x90, 0xC3])],
+            &[(".text.other", 4)]
+        );
+
+        let layout = link(&data);
+
+        // .text.other follows .text at 0x1006, the label is at 0x100A
+        assert_eq!(get_output_section(&layout, ".text").data, [0xE8, 0x05, 0, 0, 0, 0xC3, 0x90, 0x90, 0x90, 0x90, 0xC3]);
+    }
+
+    #[test]
+    fn relative_relocation_before_the_target_section_wraps_around() {
+        let data = build_object(&[(".text.other", SectionKind::Text, &[0xC3])], &[(".text.other", -2)]);
+
+        let layout = link(&data);
+
+        // .text.other is at 0x1006, the target is 0x1004, the next instruction 0x1005
+        assert_eq!(get_displacements(&layout, 1), [u32::MAX]);
+    }
+
+    #[test]
+    fn relative_relocation_into_a_merged_section_is_translated() {
+        // both calls reference "bar", the copy in .rodata.b is dropped
+        let data = build_object(
+            &[
+                (".rodata.a", SectionKind::ReadOnlyString, b"foo\0bar\0"),
+                (".rodata.b", SectionKind::ReadOnlyString, b"bar\0"),
+            ],
+            &[(".rodata.a", 4), (".rodata.b", 0)]
+        );
+
+        let layout = link(&data);
+        let target = get_output_section(&layout, ".rodata").address as u32 + 4;
+
+        assert_eq!(get_output_section(&layout, ".rodata").size, 8);
+        assert_eq!(get_displacements(&layout, 2), [target - 0x1005, target - 0x100A]);
+    }
+}
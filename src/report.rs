@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::elf::{context::{Context, SymbolEntry}, file::ElfSection, utils};
+use crate::error::{Error, ParseError, ReadError};
+use crate::gc::LiveReason;
+use crate::layout::{Layout, SectionMapping};
+
+/// Machine-readable description of a link written by `--report-json`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkReport {
+    pub output: String,
+    pub entry: String,
+    pub entry_address: usize,
+    /// End of the last output section.
+    pub end_address: usize,
+    /// Output sections in address order.
+    pub output_sections: Vec<OutputSectionReport>,
+    /// Input sections that are not placed into the output.
+    pub discarded_sections: Vec<InputSectionReport>,
+    /// Symbols defined by the script and `__start_`/`__stop_` symbols.
+    pub linker_symbols: Vec<SymbolReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutputSectionReport {
+    pub name: String,
+    pub address: usize,
+    pub size: usize,
+    pub alignment: usize,
+    /// Occupies memory only, has no contents in the file.
+    pub nobits: bool,
+    /// Placed input sections in address order.
+    pub input_sections: Vec<InputSectionReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InputSectionReport {
+    pub name: String,
+    /// Object file, the member name for archive members.
+    pub file: String,
+    /// Archive containing the file.
+    pub archive: Option<String>,
+    /// `None` for discarded sections.
+    pub address: Option<usize>,
+    pub size: usize,
+    pub alignment: usize,
+    pub relocations: usize,
+    pub reason: SectionReason,
+    /// Symbols sorted by address, empty for discarded sections.
+    pub symbols: Vec<SymbolReport>,
+}
+
+/// Why an input section is placed into the output or discarded.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SectionReason {
+    /// Defines the entry symbol.
+    Entry { symbol: String },
+    /// Matched by a `KEEP` pattern of the script.
+    Kept,
+    /// Referenced by a relocation of a live section.
+    Referenced {
+        section: String,
+        file: String,
+        symbol: Option<String>,
+    },
+    /// Not reachable from the entry symbol and `KEEP` sections.
+    Unreferenced,
+    /// Duplicate of a COMDAT group kept from another file.
+    Comdat,
+    /// Identical to the section it is folded into.
+    Folded { section: String, file: String },
+    /// Matched by `/DISCARD/` of the script.
+    Script,
+    /// Doesn't occupy memory during process execution.
+    NotAllocated,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SymbolReport {
+    /// Mangled name, the source name for local symbols.
+    pub name: String,
+    pub demangled: String,
+    pub address: usize,
+    /// `st_size` of the definition, 0 for linker symbols and symbols of
+    /// unknown size.
+    #[serde(default)]
+    pub size: usize,
+    pub local: bool,
+}
+
+impl LinkReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("cannot serialize link report")
+    }
+
+    /// Reads a report written by `--report-json`.
+    pub fn read(path: &str) -> Result<Self, Error> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|source| ReadError::Io { path: path.to_owned(), source })?;
+
+        let report = serde_json::from_str(&json)
+            .map_err(|error| ParseError::Report { path: path.to_owned(), message: error.to_string() })?;
+
+        Ok(report)
+    }
+}
+
+/// Collects output and input sections with their symbols and the reasons
+/// the sections are live (`live_reasons`) or discarded.
+pub fn generate_link_report(
+    context: &Context,
+    mapping: &SectionMapping,
+    layout: &Layout,
+    live_reasons: &HashMap<u64, LiveReason>,
+    output: &str
+) -> LinkReport {
+    // section uid -> (name, file) to describe references
+    let mut section_names = HashMap::<u64, (String, String)>::new();
+
+    for object in context.objects.iter() {
+        for section in object.sections() {
+            let section = (*section).borrow();
+            section_names.insert(section.uid, (section.name.to_owned(), section.get_file_name()));
+        }
+    }
+
+    let get_reason = |section: &ElfSection| -> SectionReason {
+        if let Some(target) = layout.merged_sections.get_folded_target(section.uid) {
+            let (section, file) = section_names[&target].clone();
+            return SectionReason::Folded { section, file };
+        }
+
+        if section.discarded {
+            return SectionReason::Comdat;
+        }
+
+        if mapping.discarded.contains(&section.uid) {
+            return SectionReason::Script;
+        }
+
+        // live sections without SHF_ALLOC are not placed either
+        if !section.is_alloc() && !layout.section_addresses.contains_key(&section.uid) {
+            return SectionReason::NotAllocated;
+        }
+
+        match live_reasons.get(&section.uid) {
+            Some(LiveReason::Entry(symbol)) => SectionReason::Entry { symbol: symbol.to_owned() },
+            Some(LiveReason::Kept) => SectionReason::Kept,
+
+            Some(LiveReason::Referenced { section, symbol, .. }) => {
+                let (section, file) = section_names[section].clone();
+
+                // section symbols have no names
+                let symbol = symbol
+                    .as_deref()
+                    .map(utils::get_local_symbol_name)
+                    .filter(|symbol| !symbol.is_empty())
+                    .map(str::to_owned);
+
+                SectionReason::Referenced { section, file, symbol }
+            }
+
+            None => SectionReason::Unreferenced,
+        }
+    };
+
+    let mut section_symbols = layout.get_section_symbols(context);
+
+    let mut output_sections = layout.output_sections.iter().collect::<Vec<_>>();
+    output_sections.sort_by_key(|output_section| output_section.address);
+
+    let output_sections = output_sections
+        .into_iter()
+        .map(|output_section| OutputSectionReport {
+            name: output_section.name.to_owned(),
+            address: output_section.address,
+            size: output_section.size,
+            alignment: output_section.alignment,
+            nobits: output_section.nobits,
+            input_sections: output_section.input_sections
+                .iter()
+                .map(|section| {
+                    let section = (*section).borrow();
+
+                    let mut symbols = section_symbols
+                        .remove(&section.uid)
+                        .unwrap_or_default();
+
+                    symbols.sort_unstable();
+
+                    let mut report = get_input_section_report(&section, get_reason(&section));
+                    report.address = Some(layout.get_section_address(&section));
+                    report.symbols = symbols
+                        .into_iter()
+                        .filter(|(_, symbol_name)| !utils::get_local_symbol_name(symbol_name).is_empty())
+                        .map(|(address, symbol_name)| get_symbol_report(context, symbol_name, address))
+                        .collect();
+
+                    report
+                })
+                .collect(),
+        })
+        .collect();
+
+    let mut discarded_sections = Vec::new();
+
+    for object in context.objects.iter() {
+        for section in object.sections() {
+            let section = (*section).borrow();
+
+            if section.metadata || layout.section_addresses.contains_key(&section.uid) {
+                continue;
+            }
+
+            discarded_sections.push(get_input_section_report(&section, get_reason(&section)));
+        }
+    }
+
+    let mut linker_symbols = layout.symbols
+        .iter()
+        .map(|(symbol_name, address)| get_symbol_report(context, symbol_name, *address))
+        .collect::<Vec<_>>();
+
+    linker_symbols.sort_unstable_by(|a, b| (a.address, &a.name).cmp(&(b.address, &b.name)));
+
+    LinkReport {
+        output: output.to_owned(),
+        entry: layout.entry.to_owned(),
+        entry_address: layout.entry_address,
+        end_address: layout.end_address,
+        output_sections,
+        discarded_sections,
+        linker_symbols,
+    }
+}
+
+/// Describes an unplaced section, the caller sets the address and symbols.
+fn get_input_section_report(section: &ElfSection, reason: SectionReason) -> InputSectionReport {
+    let (file, archive) = section.file
+        .upgrade()
+        .map(|file| {
+            let file = (*file).borrow();
+            (file.filename.to_owned(), file.archive.to_owned())
+        })
+        .unwrap_or_default();
+
+    InputSectionReport {
+        name: section.name.to_owned(),
+        file,
+        archive,
+        address: None,
+        size: section.size,
+        alignment: section.alignment,
+        relocations: section.relocations.len(),
+        reason,
+        symbols: Vec::new(),
+    }
+}
+
+fn get_symbol_report(context: &Context, symbol_name: &str, address: usize) -> SymbolReport {
+    let name = utils::get_local_symbol_name(symbol_name);
+
+    let size = match context.symbol_map.get(symbol_name) {
+        Some(SymbolEntry::Resolved(weak_symbol)) => weak_symbol
+            .upgrade()
+            .map_or(0, |symbol| (*symbol).borrow().size),
+        _ => 0,
+    };
+
+    SymbolReport {
+        name: name.to_owned(),
+        demangled: utils::demangle(name),
+        address,
+        size,
+        local: symbol_name.contains(utils::LOCAL_SYMBOL_SUFFIX),
+    }
+}
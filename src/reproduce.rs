@@ -0,0 +1,360 @@
+use std::{io::Write, path::{Component, Path}};
+use crate::error::{Error, ParseError, ReadError, WriteError};
+use crate::options::{self, Options};
+use crate::writer;
+
+/// Command line inside the reproducer, one argument per line.
+const RESPONSE_FILE: &str = "response.txt";
+
+const BLOCK_SIZE: usize = 512;
+
+/// Writes a tar archive to reproduce the link: input files and the
+/// linker script under `inputs/`, the command line (`response.txt`),
+/// library search results (`libraries.txt`) and the kld version.
+///
+/// Paths of the command line are relative to the root directory of the
+/// archive, outputs are written to the root directory by `replay`.
+pub fn write_reproducer(path: &str, options: &Options) -> Result<(), Error> {
+    let root = get_root_name(path);
+
+    let mut tar = Vec::new();
+    let mut replay_options = options.clone();
+
+    let inputs = replay_options.objects
+        .iter_mut()
+        .chain(replay_options.archives.iter_mut())
+        .chain(replay_options.script.iter_mut());
+
+    for input in inputs {
+        let data = std::fs::read(&*input)
+            .map_err(|source| ReadError::Io { path: input.to_owned(), source })?;
+
+        let name = get_input_name(input);
+        append_file(&mut tar, &format!("{}/{}", root, name), &data);
+
+        *input = name;
+    }
+
+    let outputs = std::iter::once(&mut replay_options.output)
+        .chain(replay_options.map.iter_mut())
+        .chain(replay_options.report_json.iter_mut())
+        .chain(replay_options.emit_graph.iter_mut())
+        .chain(replay_options.time_trace_file.iter_mut())
+        .chain(replay_options.why_extract.iter_mut().filter(|path| *path != "-"));
+
+    for output in outputs {
+        *output = get_file_name(output);
+    }
+
+    let mut response = replay_options.to_arguments().join("\n");
+    response.push('\n');
+
+    append_file(&mut tar, &format!("{}/{}", root, RESPONSE_FILE), response.as_bytes());
+
+    let mut libraries = String::new();
+
+    for (library_name, library_path) in options.found_libraries.iter() {
+        let library_path = library_path.as_deref().unwrap_or("not found");
+        libraries.push_str(&format!("-l{}: {}\n", library_name, library_path));
+    }
+
+    append_file(&mut tar, &format!("{}/libraries.txt", root), libraries.as_bytes());
+    append_file(&mut tar, &format!("{}/version.txt", root), format!("kld {}\n", env!("CARGO_PKG_VERSION")).as_bytes());
+
+    // end of archive
+    tar.resize(tar.len() + 2 * BLOCK_SIZE, 0);
+
+    writer::write_file_atomically(path, &tar)?;
+
+    Ok(())
+}
+
+/// Extracts a reproducer into the current directory and returns the
+/// options of its command line. The current directory is changed to the
+/// root directory of the archive, so the command line finds its inputs.
+///
+/// The root directory must not exist, so replaying never overwrites files.
+pub fn replay(path: &str) -> Result<Options, Error> {
+    let data = std::fs::read(path)
+        .map_err(|source| ReadError::Io { path: path.to_owned(), source })?;
+
+    let error = |message: String| ParseError::Reproducer { path: path.to_owned(), message };
+
+    let entries = read_entries(&data).map_err(error)?;
+
+    let root = entries
+        .iter()
+        .find_map(|(name, _)| name.strip_suffix(&format!("/{}", RESPONSE_FILE)))
+        .ok_or_else(|| error(format!("no {}", RESPONSE_FILE)))?
+        .to_owned();
+
+    if Path::new(&root).exists() {
+        return Err(error(format!("`{}` already exists, remove it to replay", root)).into());
+    }
+
+    for (name, contents) in entries.iter() {
+        // entries must stay inside the root directory
+        let is_safe = name.starts_with(&format!("{}/", root)) && Path::new(name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+
+        if !is_safe {
+            return Err(error(format!("unsafe entry path `{}`", name)).into());
+        }
+
+        if let Some(directory) = Path::new(name).parent() {
+            std::fs::create_dir_all(directory)
+                .map_err(|source| WriteError { path: directory.to_string_lossy().into_owned(), source })?;
+        }
+
+        // duplicate entries are not overwritten either
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(name)
+            .and_then(|mut file| file.write_all(contents))
+            .map_err(|source| WriteError { path: name.to_owned(), source })?;
+    }
+
+    std::env::set_current_dir(&root)
+        .map_err(|source| ReadError::Io { path: root.to_owned(), source })?;
+
+    log::debug!("replay in {}", root);
+
+    let response = std::fs::read_to_string(RESPONSE_FILE)
+        .map_err(|source| ReadError::Io { path: RESPONSE_FILE.to_owned(), source })?;
+
+    let arguments = response
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+
+    options::parse_options(arguments.into_iter())
+}
+
+/// Returns the archive file name without the extension, `repro` if it
+/// has none.
+fn get_root_name(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or_else(|| "repro".to_owned())
+}
+
+/// Maps an input path into `inputs/`, parent directories become `__`.
+fn get_input_name(path: &str) -> String {
+    let components = Path::new(path)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            Component::ParentDir => Some("__".to_owned()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    format!("inputs/{}", components.join("/"))
+}
+
+fn get_file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_owned())
+}
+
+/// Appends a regular file in the ustar format. Names longer than the
+/// header field are stored in a PAX extended header.
+fn append_file(tar: &mut Vec<u8>, name: &str, data: &[u8]) {
+    if name.len() > 100 {
+        // the record length counts its own digits
+        let record_size = |digits: usize| digits + " path=\n".len() + name.len();
+        let mut digits = 1;
+
+        while record_size(digits).to_string().len() != digits {
+            digits += 1;
+        }
+
+        let record = format!("{} path={}\n", record_size(digits), name);
+        append_entry(tar, "PaxHeader", b'x', record.as_bytes());
+    }
+
+    append_entry(tar, name, b'0', data);
+}
+
+fn append_entry(tar: &mut Vec<u8>, name: &str, kind: u8, data: &[u8]) {
+    let mut header = [0u8; BLOCK_SIZE];
+
+    let name = name.as_bytes();
+    let name = &name[..name.len().min(100)];
+
+    header[..name.len()].copy_from_slice(name);
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+    // zero modification time keeps archives reproducible
+    header[136..148].copy_from_slice(b"00000000000\0");
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    // the checksum is computed with the checksum field filled by spaces
+    header[148..156].fill(b' ');
+    let checksum = header.iter().map(|byte| *byte as u32).sum::<u32>();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+    tar.extend_from_slice(&header);
+    tar.extend_from_slice(data);
+    tar.resize(tar.len().next_multiple_of(BLOCK_SIZE), 0);
+}
+
+/// Reads regular files of a ustar archive, PAX `path` records override
+/// the names of the following entries.
+fn read_entries(data: &[u8]) -> Result<Vec<(String, &[u8])>, String> {
+    let mut entries = Vec::new();
+    let mut position = 0;
+    let mut pax_name = None;
+
+    while let Some(header) = data.get(position..position + BLOCK_SIZE) {
+        if header.iter().all(|byte| *byte == 0) {
+            break;
+        }
+
+        let size = read_field(&header[124..136]);
+        let size = usize::from_str_radix(size.trim(), 8)
+            .map_err(|_| format!("invalid entry size at offset {}", position))?;
+
+        let start = position + BLOCK_SIZE;
+
+        let contents = start.checked_add(size)
+            .and_then(|end| data.get(start..end))
+            .ok_or_else(|| format!("entry at offset {} is out of bounds", position))?;
+
+        let mut name = read_field(&header[..100]);
+        let prefix = read_field(&header[345..500]);
+
+        if !prefix.is_empty() {
+            name = format!("{}/{}", prefix, name);
+        }
+
+        match header[156] {
+            b'x' => {
+                pax_name = String::from_utf8_lossy(contents)
+                    .lines()
+                    .find_map(|record| record.split_once(" path=").map(|(_, path)| path.to_owned()));
+            }
+
+            b'0' | 0 => entries.push((pax_name.take().unwrap_or(name), contents)),
+
+            _ => pax_name = None,
+        }
+
+        position = start + size.next_multiple_of(BLOCK_SIZE);
+    }
+
+    Ok(entries)
+}
+
+/// Reads a NUL-terminated header field.
+fn read_field(field: &[u8]) -> String {
+    let end = field.iter().position(|byte| *byte == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut tar = Vec::new();
+
+        for (name, data) in files {
+            append_file(&mut tar, name, data);
+        }
+
+        tar.resize(tar.len() + 2 * BLOCK_SIZE, 0);
+        tar
+    }
+
+    #[test]
+    fn files_round_trip() {
+        let long_name = format!("repro/inputs/{}/main.o", "directory/".repeat(12));
+        let longer_name = format!("repro/inputs/{}.o", "x".repeat(1000));
+
+        let files: &[(&str, &[u8])] = &[
+            ("repro/response.txt", b"-o\nout.kex\n"),
+            (&long_name, &[0x7F, b'E', b'L', b'F']),
+            ("repro/empty.txt", b""),
+            (&longer_name, &[0; 1000]),
+            ("repro/block.bin", &[1; BLOCK_SIZE]),
+        ];
+
+        let tar = write_archive(files);
+
+        assert_eq!(tar.len() % BLOCK_SIZE, 0);
+
+        let entries = read_entries(&tar).unwrap();
+
+        assert_eq!(entries.len(), files.len());
+
+        for ((name, contents), (expected_name, expected_contents)) in entries.iter().zip(files) {
+            assert_eq!(name, expected_name);
+            assert_eq!(contents, expected_contents);
+        }
+    }
+
+    #[test]
+    fn long_names_are_stored_in_pax_records() {
+        for length in [101, 993, 994, 995, 9_990] {
+            let name = "n".repeat(length);
+            let tar = write_archive(&[(&name, b"data")]);
+
+            // PAX header, its record, the file header and its contents
+            assert_eq!(tar[156], b'x');
+            assert_eq!(tar[2 * BLOCK_SIZE..].len() % BLOCK_SIZE, 0);
+
+            let record_size = read_field(&tar[124..136]);
+            let record_size = usize::from_str_radix(&record_size, 8).unwrap();
+            let record = std::str::from_utf8(&tar[BLOCK_SIZE..BLOCK_SIZE + record_size]).unwrap();
+
+            // the record length counts the whole record
+            let (length_field, path) = record.split_once(' ').unwrap();
+
+            assert_eq!(length_field.parse::<usize>().unwrap(), record.len());
+            assert_eq!(path, format!("path={}\n", name));
+
+            // the ustar name is truncated
+            let header = &tar[(BLOCK_SIZE + record_size.next_multiple_of(BLOCK_SIZE))..];
+
+            assert_eq!(read_field(&header[..100]), name[..100]);
+            assert_eq!(read_entries(&tar).unwrap(), [(name, &b"data"[..])]);
+        }
+    }
+
+    #[test]
+    fn headers_have_valid_checksums() {
+        let tar = write_archive(&[("repro/version.txt", b"kld\n")]);
+        let header = &tar[..BLOCK_SIZE];
+
+        let checksum = read_field(&header[148..156]);
+        let checksum = u32::from_str_radix(checksum.trim(), 8).unwrap();
+
+        let expected = header
+            .iter()
+            .enumerate()
+            .map(|(index, byte)| if (148..156).contains(&index) { b' ' as u32 } else { *byte as u32 })
+            .sum::<u32>();
+
+        assert_eq!(checksum, expected);
+        assert_eq!(&header[257..263], b"ustar\0");
+    }
+
+    #[test]
+    fn truncated_archives_are_rejected() {
+        let tar = write_archive(&[("repro/a.o", &[1; 100])]);
+
+        assert!(read_entries(&tar[..BLOCK_SIZE + 50]).is_err());
+    }
+}
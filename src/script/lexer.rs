@@ -0,0 +1,254 @@
+use super::parser::ParseError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// Identifier in expressions or a file/section name pattern.
+    Name(String),
+    Number(u64),
+    String(String),
+    Operator(&'static str),
+    End,
+}
+
+/// Lexer mode: linker scripts tokenize names and expressions differently,
+/// e.g. `.note.GNU-stack` is one name while `a-b` is a subtraction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Expression,
+    Name,
+}
+
+/// Sorted by length so the longest operator is matched first.
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=",
+    "+=", "-=", "*=", "/=", "&=", "|=", "<<", ">>", "==", "!=", "<=", ">=", "&&", "||",
+    "(", ")", "{", "}", ";", ",", ":", "=", "+", "-", "*", "/", "%", "<", ">", "&", "|", "~", "!", "?",
+];
+
+#[derive(Debug, Clone, Copy)]
+pub struct Lexer<'a> {
+    source: &'a str,
+    position: usize,
+    line: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            position: 0,
+            line: 1,
+        }
+    }
+
+    pub fn peek(&self, mode: Mode) -> Result<Token, ParseError> {
+        let mut lexer = *self;
+        lexer.next(mode)
+    }
+
+    pub fn next(&mut self, mode: Mode) -> Result<Token, ParseError> {
+        self.skip_whitespace_and_comments()?;
+
+        let rest = &self.source[self.position..];
+
+        let Some(first) = rest.chars().next() else {
+            return Ok(Token::End);
+        };
+
+        if first == '"' {
+            return self.read_string();
+        }
+
+        match mode {
+            Mode::Name => {
+                let length = rest
+                    .find(|c: char| c.is_whitespace() || "(){};,=:\"".contains(c))
+                    .unwrap_or(rest.len());
+
+                if length > 0 {
+                    self.position += length;
+                    return Ok(Token::Name(rest[..length].to_owned()));
+                }
+            }
+
+            Mode::Expression => {
+                if first.is_ascii_digit() {
+                    return self.read_number();
+                }
+
+                if first.is_ascii_alphabetic() || "_.$".contains(first) {
+                    let length = rest
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || "_.$".contains(c)))
+                        .unwrap_or(rest.len());
+
+                    self.position += length;
+                    return Ok(Token::Name(rest[..length].to_owned()));
+                }
+            }
+        }
+
+        for operator in OPERATORS {
+            if rest.starts_with(operator) {
+                self.position += operator.len();
+                return Ok(Token::Operator(operator));
+            }
+        }
+
+        Err(self.error(format!("unexpected character `{}`", first)))
+    }
+
+    pub fn error(&self, message: String) -> ParseError {
+        ParseError {
+            line: self.line,
+            message,
+        }
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), ParseError> {
+        loop {
+            let rest = &self.source[self.position..];
+
+            if let Some(c) = rest.chars().next().filter(|c| c.is_whitespace()) {
+                if c == '\n' {
+                    self.line += 1;
+                }
+
+                self.position += c.len_utf8();
+            } else if rest.starts_with("/*") {
+                let end = rest
+                    .find("*/")
+                    .ok_or_else(|| self.error("unterminated comment".to_owned()))?;
+
+                self.line += rest[..end].matches('\n').count();
+                self.position += end + 2;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn read_string(&mut self) -> Result<Token, ParseError> {
+        let rest = &self.source[self.position + 1..];
+
+        let end = rest
+            .find('"')
+            .ok_or_else(|| self.error("unterminated string".to_owned()))?;
+
+        self.line += rest[..end].matches('\n').count();
+        self.position += end + 2;
+
+        Ok(Token::String(rest[..end].to_owned()))
+    }
+
+    fn read_number(&mut self) -> Result<Token, ParseError> {
+        let rest = &self.source[self.position..];
+
+        let length = rest
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(rest.len());
+
+        let text = &rest[..length];
+        self.position += length;
+
+        let (text, multiplier) = match text.as_bytes().last() {
+            Some(b'K' | b'k') => (&text[..text.len() - 1], 1024),
+            Some(b'M' | b'm') => (&text[..text.len() - 1], 1024 * 1024),
+            _ => (text, 1),
+        };
+
+        let value = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            u64::from_str_radix(hex, 16)
+        } else if let Some(hex) = text.strip_suffix(['h', 'H']) {
+            u64::from_str_radix(hex, 16)
+        } else if text.len() > 1 && text.starts_with('0') {
+            u64::from_str_radix(&text[1..], 8)
+        } else {
+            text.parse::<u64>()
+        };
+
+        value
+            .map(|value| Token::Number(value.wrapping_mul(multiplier)))
+            .map_err(|_| self.error(format!("invalid number `{}`", &rest[..length])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokenize(source: &str, mode: Mode) -> Vec<Token> {
+        let mut lexer = Lexer::new(source);
+        let mut tokens = Vec::new();
+
+        loop {
+            match lexer.next(mode).unwrap() {
+                Token::End => return tokens,
+                token => tokens.push(token),
+            }
+        }
+    }
+
+    fn name(name: &str) -> Token {
+        Token::Name(name.to_owned())
+    }
+
+    #[test]
+    fn expression_mode_splits_operators() {
+        assert_eq!(
+            tokenize("a-b <<= .+1", Mode::Expression),
+            [name("a"), Token::Operator("-"), name("b"), Token::Operator("<<="), name("."), Token::Operator("+"), Token::Number(1)]
+        );
+    }
+
+    #[test]
+    fn name_mode_keeps_patterns_whole() {
+        assert_eq!(
+            tokenize("*(.note.GNU-stack .text.[a-z]*)", Mode::Name),
+            [name("*"), Token::Operator("("), name(".note.GNU-stack"), name(".text.[a-z]*"), Token::Operator(")")]
+        );
+    }
+
+    #[test]
+    fn reads_numbers() {
+        assert_eq!(
+            tokenize("10 0x10 0X1f 10h 010 4K 2M 0", Mode::Expression),
+            [10, 16, 31, 16, 8, 4096, 2 * 1024 * 1024, 0].map(Token::Number)
+        );
+
+        assert!(Lexer::new("0xZZ").next(Mode::Expression).is_err());
+    }
+
+    #[test]
+    fn reads_strings() {
+        assert_eq!(tokenize("\"a b\"", Mode::Name), [Token::String("a b".to_owned())]);
+        assert!(Lexer::new("\"a").next(Mode::Expression).is_err());
+    }
+
+    #[test]
+    fn skips_comments_and_counts_lines() {
+        let mut lexer = Lexer::new("/* one\ntwo */\n\nx");
+
+        assert_eq!(lexer.next(Mode::Expression).unwrap(), name("x"));
+        assert_eq!(lexer.error(String::new()).line, 4);
+
+        let error = Lexer::new("\n/* open").next(Mode::Expression).unwrap_err();
+
+        assert_eq!((error.line, error.message.as_str()), (2, "unterminated comment"));
+    }
+
+    #[test]
+    fn peek_doesnt_advance() {
+        let mut lexer = Lexer::new("a b");
+
+        assert_eq!(lexer.peek(Mode::Expression).unwrap(), name("a"));
+        assert_eq!(lexer.next(Mode::Expression).unwrap(), name("a"));
+        assert_eq!(lexer.next(Mode::Expression).unwrap(), name("b"));
+    }
+
+    #[test]
+    fn rejects_unexpected_characters() {
+        let error = Lexer::new("@").next(Mode::Expression).unwrap_err();
+
+        assert_eq!(error.message, "unexpected character `@`");
+    }
+}
@@ -0,0 +1,394 @@
+mod lexer;
+mod parser;
+
+use std::collections::HashSet;
+
+pub use parser::{parse, ParseError};
+
+/// Built-in script used when no `-T` option is given.
+pub const DEFAULT_SCRIPT: &str = include_str!("default.ld");
+
+/// Subset of the GNU linker script language.
+#[derive(Debug)]
+pub struct Script {
+    /// Entry point symbol set by `ENTRY(symbol)`.
+    pub entry: Option<String>,
+    /// Top-level statements and `SECTIONS` contents in source order.
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Debug)]
+pub enum Statement {
+    Assignment(Assignment),
+    Assert(Assert),
+    OutputSection(OutputSectionDescription),
+}
+
+/// Location counter name.
+pub const LOCATION_COUNTER: &str = ".";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssignmentOperator {
+    Assign,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
+}
+
+#[derive(Debug)]
+pub struct Assignment {
+    /// Symbol name or `.` for the location counter.
+    pub symbol: String,
+    pub operator: AssignmentOperator,
+    pub expression: Expression,
+    /// Defined only if referenced and not defined by input files.
+    pub provide: bool,
+}
+
+#[derive(Debug)]
+pub struct Assert {
+    pub expression: Expression,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub struct OutputSectionDescription {
+    pub name: String,
+    /// Start address, the aligned location counter when not set.
+    pub address: Option<Expression>,
+    /// Minimal alignment set by `ALIGN(expression)` after the colon.
+    pub alignment: Option<Expression>,
+    /// `(NOLOAD)` section occupies memory only.
+    pub noload: bool,
+    pub commands: Vec<OutputSectionCommand>,
+    /// Gap filler set by `=fill` after the closing brace.
+    pub fill: Option<Expression>,
+}
+
+impl OutputSectionDescription {
+    /// Input sections placed into `/DISCARD/` are removed from the output.
+    pub fn is_discard(&self) -> bool {
+        self.name == "/DISCARD/"
+    }
+}
+
+#[derive(Debug)]
+pub enum OutputSectionCommand {
+    Assignment(Assignment),
+    Assert(Assert),
+    InputSection(InputSectionDescription),
+}
+
+#[derive(Debug)]
+pub struct InputSectionDescription {
+    /// Sequential number of the description in the script.
+    pub id: usize,
+    /// Sections matched by `KEEP(...)` are never garbage collected.
+    pub keep: bool,
+    pub flags: SectionFlagsFilter,
+    pub file_pattern: Pattern,
+    pub section_patterns: Vec<Pattern>,
+}
+
+impl InputSectionDescription {
+    pub fn matches(&self, filename: &str, section_name: &str, section_flags: u64) -> bool {
+        self.flags.matches(section_flags) &&
+        self.file_pattern.matches(filename) &&
+        self.section_patterns.iter().any(|pattern| pattern.matches(section_name))
+    }
+}
+
+/// `INPUT_SECTION_FLAGS(SHF_A & !SHF_B)` filter.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SectionFlagsFilter {
+    pub required: u64,
+    pub forbidden: u64,
+}
+
+impl SectionFlagsFilter {
+    pub fn matches(&self, flags: u64) -> bool {
+        (flags & self.required) == self.required && (flags & self.forbidden) == 0
+    }
+}
+
+/// Wildcard pattern with `*`, `?` and `[chars]` support.
+#[derive(Debug, Clone)]
+pub struct Pattern(pub String);
+
+impl Pattern {
+    /// Matches iteratively, a mismatch after `*` retries with the last `*`
+    /// consuming one more character, so matching takes O(pattern * value).
+    pub fn matches(&self, value: &str) -> bool {
+        let pattern = self.0.chars().collect::<Vec<_>>();
+        let value = value.chars().collect::<Vec<_>>();
+
+        let mut pattern_position = 0;
+        let mut value_position = 0;
+        // positions after the last `*` and in the value it resumes from
+        let mut backtrack = None;
+
+        while value_position < value.len() {
+            if pattern.get(pattern_position) == Some(&'*') {
+                pattern_position += 1;
+                backtrack = Some((pattern_position, value_position));
+                continue;
+            }
+
+            if let Some(next_position) = Self::match_char(&pattern, pattern_position, value[value_position]) {
+                pattern_position = next_position;
+                value_position += 1;
+                continue;
+            }
+
+            let Some((star_position, star_value_position)) = backtrack else {
+                return false;
+            };
+
+            pattern_position = star_position;
+            value_position = star_value_position + 1;
+            backtrack = Some((star_position, value_position));
+        }
+
+        pattern[pattern_position..].iter().all(|&c| c == '*')
+    }
+
+    /// Matches a character against the pattern element at `position`
+    /// (a character, `?` or `[chars]`), returns the position of the next
+    /// element.
+    fn match_char(pattern: &[char], position: usize, c: char) -> Option<usize> {
+        match *pattern.get(position)? {
+            '?' => Some(position + 1),
+
+            '[' => {
+                let Some(close) = pattern[position..].iter().skip(2).position(|&c| c == ']').map(|p| position + p + 2) else {
+                    return (c == '[').then_some(position + 1);
+                };
+
+                let (negate, set) = match pattern[position + 1] {
+                    '!' | '^' => (true, &pattern[position + 2..close]),
+                    _ => (false, &pattern[position + 1..close]),
+                };
+
+                let mut found = false;
+                let mut index = 0;
+
+                while index < set.len() {
+                    if index + 2 < set.len() && set[index + 1] == '-' {
+                        found |= set[index] <= c && c <= set[index + 2];
+                        index += 3;
+                    } else {
+                        found |= set[index] == c;
+                        index += 1;
+                    }
+                }
+
+                (found != negate).then_some(close + 1)
+            }
+
+            p => (p == c).then_some(position + 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOperator {
+    Negate,
+    Not,
+    BitwiseNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOperator {
+    Multiply,
+    Divide,
+    Remainder,
+    Add,
+    Subtract,
+    ShiftLeft,
+    ShiftRight,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+    BitwiseAnd,
+    BitwiseOr,
+    And,
+    Or,
+}
+
+#[derive(Debug)]
+pub enum Expression {
+    Number(u64),
+    Symbol(String),
+    LocationCounter,
+    Unary(UnaryOperator, Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+    Conditional(Box<Expression>, Box<Expression>, Box<Expression>),
+    /// `ALIGN(align)` or `ALIGN(expression, align)`.
+    Align(Option<Box<Expression>>, Box<Expression>),
+    Absolute(Box<Expression>),
+    Address(String),
+    SizeOf(String),
+    SizeOfHeaders,
+    Defined(String),
+    Max(Box<Expression>, Box<Expression>),
+    Min(Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    /// Returns `true` if the value doesn't depend on symbols, sections
+    /// or the location counter.
+    pub fn is_constant(&self) -> bool {
+        match self {
+            Self::Number(_) | Self::SizeOfHeaders => true,
+            Self::Symbol(_) | Self::LocationCounter | Self::Address(_) | Self::SizeOf(_) | Self::Defined(_) => false,
+            Self::Unary(_, operand) | Self::Absolute(operand) => operand.is_constant(),
+            Self::Binary(_, left, right) | Self::Max(left, right) | Self::Min(left, right) => {
+                left.is_constant() && right.is_constant()
+            }
+            Self::Conditional(condition, left, right) => {
+                condition.is_constant() && left.is_constant() && right.is_constant()
+            }
+            Self::Align(expression, alignment) => {
+                expression.as_ref().is_some_and(|expression| expression.is_constant()) && alignment.is_constant()
+            }
+        }
+    }
+}
+
+impl Script {
+    /// Returns names of all symbols assigned by the script.
+    pub fn defined_symbols(&self) -> HashSet<&str> {
+        let mut symbols = HashSet::new();
+
+        for statement in self.statements.iter() {
+            match statement {
+                Statement::Assignment(assignment) => {
+                    symbols.insert(assignment.symbol.as_str());
+                }
+
+                Statement::OutputSection(output_section) => {
+                    for command in output_section.commands.iter() {
+                        if let OutputSectionCommand::Assignment(assignment) = command {
+                            symbols.insert(assignment.symbol.as_str());
+                        }
+                    }
+                }
+
+                Statement::Assert(_) => {}
+            }
+        }
+
+        symbols.remove(LOCATION_COUNTER);
+        symbols
+    }
+
+    pub fn input_section_descriptions(&self) -> impl Iterator<Item = (&OutputSectionDescription, &InputSectionDescription)> {
+        self.statements
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::OutputSection(output_section) => Some(output_section),
+                _ => None,
+            })
+            .flat_map(|output_section| {
+                output_section.commands
+                    .iter()
+                    .filter_map(move |command| match command {
+                        OutputSectionCommand::InputSection(input_section) => Some((output_section, input_section)),
+                        _ => None,
+                    })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, value: &str) -> bool {
+        Pattern(pattern.to_owned()).matches(value)
+    }
+
+    #[test]
+    fn pattern_matches_literals_and_wildcards() {
+        assert!(matches(".text", ".text"));
+        assert!(!matches(".text", ".text.f"));
+        assert!(matches(".text.*", ".text.f"));
+        assert!(matches(".text.*", ".text."));
+        assert!(!matches(".text.*", ".text"));
+        assert!(matches("*", ""));
+        assert!(matches("*(*)", "a(b)"));
+        assert!(matches(".data?", ".data1"));
+        assert!(!matches(".data?", ".data"));
+        assert!(matches("*crt*.o", "/lib/crtbegin.o"));
+        assert!(!matches("*crt*.o", "/lib/crtbegin.obj"));
+    }
+
+    #[test]
+    fn pattern_backtracks_to_the_last_star() {
+        assert!(matches("*a*b", "xaxxaxb"));
+        assert!(matches("*.rodata.str1.*", ".rodata.str1.rodata.str1.1"));
+        assert!(!matches("*a*b", "xaxxax"));
+        assert!(matches("a*b*c*", "abcabc"));
+    }
+
+    #[test]
+    fn pattern_matches_character_classes() {
+        assert!(matches(".text.[a-c]", ".text.b"));
+        assert!(!matches(".text.[a-c]", ".text.d"));
+        assert!(matches(".text.[!a-c]", ".text.d"));
+        assert!(matches(".text.[^a-c]", ".text.d"));
+        assert!(!matches(".text.[!a-c]", ".text.a"));
+        assert!(matches("[]x]", "]"));
+        assert!(matches("[xy-]", "-"));
+        // unterminated class is a literal `[`
+        assert!(matches("a[b", "a[b"));
+        assert!(!matches("a[b", "ab"));
+    }
+
+    #[test]
+    fn pattern_with_many_stars_matches_in_polynomial_time() {
+        let pattern = "*a".repeat(32);
+        let value = "a".repeat(31) + "b";
+
+        assert!(!matches(&pattern, &value));
+        assert!(matches(&pattern, &"a".repeat(64)));
+    }
+
+    #[test]
+    fn section_flags_filter_requires_and_forbids_flags() {
+        let filter = SectionFlagsFilter {
+            required: object::elf::SHF_ALLOC as u64,
+            forbidden: object::elf::SHF_WRITE as u64,
+        };
+
+        assert!(filter.matches((object::elf::SHF_ALLOC | object::elf::SHF_EXECINSTR) as u64));
+        assert!(!filter.matches((object::elf::SHF_ALLOC | object::elf::SHF_WRITE) as u64));
+        assert!(!filter.matches(object::elf::SHF_EXECINSTR as u64));
+    }
+
+    #[test]
+    fn defined_symbols_exclude_the_location_counter() {
+        let script = parse("a = 1; SECTIONS { . = 0x100; .text : { b = .; *(.text) } }").unwrap();
+        let mut symbols = script.defined_symbols().into_iter().collect::<Vec<_>>();
+
+        symbols.sort_unstable();
+
+        assert_eq!(symbols, ["a", "b"]);
+    }
+
+    #[test]
+    fn default_script_parses() {
+        let script = parse(DEFAULT_SCRIPT).unwrap();
+
+        assert_eq!(script.entry.as_deref(), Some("_start"));
+        assert!(script.defined_symbols().contains("__stack_top"));
+    }
+}
@@ -0,0 +1,817 @@
+use std::fmt;
+use object::elf;
+use super::lexer::{Lexer, Mode, Token};
+use super::{
+    Assert, Assignment, AssignmentOperator, BinaryOperator, Expression, InputSectionDescription,
+    OutputSectionCommand, OutputSectionDescription, Pattern, Script, SectionFlagsFilter, Statement,
+    UnaryOperator, LOCATION_COUNTER,
+};
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Parses a linker script.
+pub fn parse(source: &str) -> Result<Script, ParseError> {
+    Parser {
+        lexer: Lexer::new(source),
+        input_section_count: 0,
+    }
+    .parse_script()
+}
+
+struct Parser<'a> {
+    lexer: Lexer<'a>,
+    input_section_count: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse_script(&mut self) -> Result<Script, ParseError> {
+        let mut script = Script {
+            entry: None,
+            statements: Vec::new(),
+        };
+
+        loop {
+            match self.lexer.peek(Mode::Expression)? {
+                Token::End => break,
+
+                Token::Name(name) if name == "ENTRY" => {
+                    self.lexer.next(Mode::Expression)?;
+                    self.expect("(")?;
+                    script.entry = Some(self.expect_name(Mode::Expression)?);
+                    self.expect(")")?;
+                }
+
+                Token::Name(name) if name == "SECTIONS" => {
+                    self.lexer.next(Mode::Expression)?;
+                    self.expect("{")?;
+
+                    while !self.accept(Mode::Expression, "}")? {
+                        let statement = self.parse_sections_statement()?;
+                        script.statements.push(statement);
+                    }
+                }
+
+                Token::Operator(";") => {
+                    self.lexer.next(Mode::Expression)?;
+                }
+
+                _ => {
+                    let statement = self.parse_statement()?;
+                    script.statements.push(statement);
+                }
+            }
+        }
+
+        Ok(script)
+    }
+
+    /// Parses an assignment or `ASSERT`.
+    fn parse_statement(&mut self) -> Result<Statement, ParseError> {
+        match self.lexer.peek(Mode::Expression)? {
+            Token::Name(name) if name == "ASSERT" => {
+                Ok(Statement::Assert(self.parse_assert()?))
+            }
+
+            _ => {
+                Ok(Statement::Assignment(self.parse_assignment()?))
+            }
+        }
+    }
+
+    fn parse_sections_statement(&mut self) -> Result<Statement, ParseError> {
+        if self.is_assignment_or_assert()? {
+            return self.parse_statement();
+        }
+
+        Ok(Statement::OutputSection(self.parse_output_section()?))
+    }
+
+    fn is_assignment_or_assert(&self) -> Result<bool, ParseError> {
+        let mut lexer = self.lexer;
+
+        match lexer.next(Mode::Expression)? {
+            Token::Name(name) if ["ASSERT", "PROVIDE", "PROVIDE_HIDDEN"].contains(&name.as_str()) => {
+                Ok(true)
+            }
+
+            Token::Name(_) => {
+                Ok(matches!(
+                    lexer.next(Mode::Expression)?,
+                    Token::Operator(operator) if Self::get_assignment_operator(operator).is_some()
+                ))
+            }
+
+            _ => Ok(false),
+        }
+    }
+
+    fn parse_output_section(&mut self) -> Result<OutputSectionDescription, ParseError> {
+        let name = self.expect_name(Mode::Name)?;
+
+        let address = if self.lexer.peek(Mode::Expression)? == Token::Operator(":") || self.is_section_type()? {
+            None
+        } else {
+            Some(self.parse_expression()?)
+        };
+
+        let noload = if self.is_section_type()? {
+            self.expect("(")?;
+            self.expect_keyword("NOLOAD")?;
+            self.expect(")")?;
+            true
+        } else {
+            false
+        };
+
+        self.expect(":")?;
+
+        let alignment = if self.accept_name("ALIGN")? {
+            self.expect("(")?;
+            let alignment = self.parse_expression()?;
+            self.expect(")")?;
+            Some(alignment)
+        } else {
+            None
+        };
+
+        self.expect("{")?;
+
+        let mut commands = Vec::new();
+
+        while !self.accept(Mode::Expression, "}")? {
+            if self.accept(Mode::Expression, ";")? {
+                continue;
+            }
+
+            if self.is_assignment_or_assert()? {
+                match self.parse_statement()? {
+                    Statement::Assignment(assignment) => {
+                        commands.push(OutputSectionCommand::Assignment(assignment));
+                    }
+
+                    Statement::Assert(assert) => {
+                        commands.push(OutputSectionCommand::Assert(assert));
+                    }
+
+                    Statement::OutputSection(_) => unreachable!(),
+                }
+            } else {
+                commands.push(OutputSectionCommand::InputSection(self.parse_input_section()?));
+            }
+        }
+
+        let fill = if self.accept(Mode::Expression, "=")? {
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+
+        self.accept(Mode::Expression, ",")?;
+
+        Ok(OutputSectionDescription {
+            name,
+            address,
+            alignment,
+            noload,
+            commands,
+            fill,
+        })
+    }
+
+    /// Checks for the `(NOLOAD)` output section type.
+    fn is_section_type(&self) -> Result<bool, ParseError> {
+        let mut lexer = self.lexer;
+
+        Ok(
+            lexer.next(Mode::Expression)? == Token::Operator("(") &&
+            lexer.next(Mode::Expression)? == Token::Name("NOLOAD".to_owned()) &&
+            lexer.next(Mode::Expression)? == Token::Operator(")")
+        )
+    }
+
+    fn parse_input_section(&mut self) -> Result<InputSectionDescription, ParseError> {
+        let keep = self.accept_name("KEEP")?;
+
+        if keep {
+            self.expect("(")?;
+        }
+
+        let flags = if self.accept_name("INPUT_SECTION_FLAGS")? {
+            self.parse_section_flags()?
+        } else {
+            SectionFlagsFilter::default()
+        };
+
+        let file_pattern = Pattern(self.expect_name(Mode::Name)?);
+        let mut section_patterns = Vec::new();
+
+        self.expect("(")?;
+
+        while !self.accept(Mode::Name, ")")? {
+            section_patterns.push(Pattern(self.expect_name(Mode::Name)?));
+        }
+
+        if keep {
+            self.expect(")")?;
+        }
+
+        let id = self.input_section_count;
+        self.input_section_count += 1;
+
+        Ok(InputSectionDescription {
+            id,
+            keep,
+            flags,
+            file_pattern,
+            section_patterns,
+        })
+    }
+
+    fn parse_section_flags(&mut self) -> Result<SectionFlagsFilter, ParseError> {
+        let mut filter = SectionFlagsFilter::default();
+
+        self.expect("(")?;
+
+        loop {
+            let negate = self.accept(Mode::Expression, "!")?;
+            let name = self.expect_name(Mode::Expression)?;
+
+            let flag = match name.as_str() {
+                "SHF_WRITE" => elf::SHF_WRITE,
+                "SHF_ALLOC" => elf::SHF_ALLOC,
+                "SHF_EXECINSTR" => elf::SHF_EXECINSTR,
+                "SHF_MERGE" => elf::SHF_MERGE,
+                "SHF_STRINGS" => elf::SHF_STRINGS,
+                "SHF_INFO_LINK" => elf::SHF_INFO_LINK,
+                "SHF_LINK_ORDER" => elf::SHF_LINK_ORDER,
+                "SHF_GROUP" => elf::SHF_GROUP,
+                "SHF_TLS" => elf::SHF_TLS,
+                _ => return Err(self.lexer.error(format!("unknown section flag `{}`", name))),
+            } as u64;
+
+            if negate {
+                filter.forbidden |= flag;
+            } else {
+                filter.required |= flag;
+            }
+
+            if !self.accept(Mode::Expression, "&")? {
+                break;
+            }
+        }
+
+        self.expect(")")?;
+
+        Ok(filter)
+    }
+
+    fn parse_assert(&mut self) -> Result<Assert, ParseError> {
+        self.expect_keyword("ASSERT")?;
+        self.expect("(")?;
+
+        let expression = self.parse_expression()?;
+
+        self.expect(",")?;
+
+        let message = match self.lexer.next(Mode::Expression)? {
+            Token::String(message) | Token::Name(message) => message,
+            token => return Err(self.unexpected(token)),
+        };
+
+        self.expect(")")?;
+        self.accept(Mode::Expression, ";")?;
+
+        Ok(Assert {
+            expression,
+            message,
+        })
+    }
+
+    fn parse_assignment(&mut self) -> Result<Assignment, ParseError> {
+        let provide = self.accept_name("PROVIDE")? || self.accept_name("PROVIDE_HIDDEN")?;
+
+        if provide {
+            self.expect("(")?;
+        }
+
+        let symbol = self.expect_name(Mode::Expression)?;
+
+        let operator = match self.lexer.next(Mode::Expression)? {
+            Token::Operator(operator) => Self::get_assignment_operator(operator)
+                .ok_or_else(|| self.lexer.error(format!("expected assignment, found `{}`", operator)))?,
+            token => return Err(self.unexpected(token)),
+        };
+
+        let expression = self.parse_expression()?;
+
+        if provide {
+            self.expect(")")?;
+
+            if symbol == LOCATION_COUNTER {
+                return Err(self.lexer.error("cannot PROVIDE the location counter".to_owned()));
+            }
+
+            self.accept(Mode::Expression, ";")?;
+        } else {
+            self.expect(";")?;
+        }
+
+        Ok(Assignment {
+            symbol,
+            operator,
+            expression,
+            provide,
+        })
+    }
+
+    fn get_assignment_operator(operator: &str) -> Option<AssignmentOperator> {
+        match operator {
+            "=" => Some(AssignmentOperator::Assign),
+            "+=" => Some(AssignmentOperator::Add),
+            "-=" => Some(AssignmentOperator::Subtract),
+            "*=" => Some(AssignmentOperator::Multiply),
+            "/=" => Some(AssignmentOperator::Divide),
+            "<<=" => Some(AssignmentOperator::ShiftLeft),
+            ">>=" => Some(AssignmentOperator::ShiftRight),
+            "&=" => Some(AssignmentOperator::And),
+            "|=" => Some(AssignmentOperator::Or),
+            _ => None,
+        }
+    }
+
+    // expressions
+
+    fn parse_expression(&mut self) -> Result<Expression, ParseError> {
+        let condition = self.parse_binary(0)?;
+
+        if !self.accept(Mode::Expression, "?")? {
+            return Ok(condition);
+        }
+
+        let left = self.parse_expression()?;
+        self.expect(":")?;
+        let right = self.parse_expression()?;
+
+        Ok(Expression::Conditional(Box::new(condition), Box::new(left), Box::new(right)))
+    }
+
+    /// Precedence climbing over binary operators, `level` 0 binds loosest.
+    fn parse_binary(&mut self, level: usize) -> Result<Expression, ParseError> {
+        const LEVELS: &[&[(&str, BinaryOperator)]] = &[
+            &[("||", BinaryOperator::Or)],
+            &[("&&", BinaryOperator::And)],
+            &[("|", BinaryOperator::BitwiseOr)],
+            &[("&", BinaryOperator::BitwiseAnd)],
+            &[("==", BinaryOperator::Equal), ("!=", BinaryOperator::NotEqual)],
+            &[
+                ("<", BinaryOperator::Less),
+                ("<=", BinaryOperator::LessOrEqual),
+                (">", BinaryOperator::Greater),
+                (">=", BinaryOperator::GreaterOrEqual),
+            ],
+            &[("<<", BinaryOperator::ShiftLeft), (">>", BinaryOperator::ShiftRight)],
+            &[("+", BinaryOperator::Add), ("-", BinaryOperator::Subtract)],
+            &[
+                ("*", BinaryOperator::Multiply),
+                ("/", BinaryOperator::Divide),
+                ("%", BinaryOperator::Remainder),
+            ],
+        ];
+
+        if level == LEVELS.len() {
+            return self.parse_unary();
+        }
+
+        let mut left = self.parse_binary(level + 1)?;
+
+        loop {
+            let operator = match self.lexer.peek(Mode::Expression)? {
+                Token::Operator(operator) => LEVELS[level]
+                    .iter()
+                    .find(|(text, _)| *text == operator)
+                    .map(|(_, operator)| *operator),
+                _ => None,
+            };
+
+            let Some(operator) = operator else {
+                return Ok(left);
+            };
+
+            self.lexer.next(Mode::Expression)?;
+
+            let right = self.parse_binary(level + 1)?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, ParseError> {
+        let operator = match self.lexer.peek(Mode::Expression)? {
+            Token::Operator("-") => Some(UnaryOperator::Negate),
+            Token::Operator("!") => Some(UnaryOperator::Not),
+            Token::Operator("~") => Some(UnaryOperator::BitwiseNot),
+            Token::Operator("+") => {
+                self.lexer.next(Mode::Expression)?;
+                return self.parse_unary();
+            }
+            _ => None,
+        };
+
+        if let Some(operator) = operator {
+            self.lexer.next(Mode::Expression)?;
+            return Ok(Expression::Unary(operator, Box::new(self.parse_unary()?)));
+        }
+
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, ParseError> {
+        let name = match self.lexer.next(Mode::Expression)? {
+            Token::Number(value) => return Ok(Expression::Number(value)),
+
+            Token::Operator("(") => {
+                let expression = self.parse_expression()?;
+                self.expect(")")?;
+                return Ok(expression);
+            }
+
+            Token::Name(name) => name,
+
+            token => return Err(self.unexpected(token)),
+        };
+
+        match name.as_str() {
+            LOCATION_COUNTER => Ok(Expression::LocationCounter),
+
+            "SIZEOF_HEADERS" => Ok(Expression::SizeOfHeaders),
+
+            "ALIGN" => {
+                self.expect("(")?;
+                let first = self.parse_expression()?;
+
+                let expression = if self.accept(Mode::Expression, ",")? {
+                    let alignment = self.parse_expression()?;
+                    Expression::Align(Some(Box::new(first)), Box::new(alignment))
+                } else {
+                    Expression::Align(None, Box::new(first))
+                };
+
+                self.expect(")")?;
+                Ok(expression)
+            }
+
+            "ABSOLUTE" => {
+                self.expect("(")?;
+                let expression = self.parse_expression()?;
+                self.expect(")")?;
+                Ok(Expression::Absolute(Box::new(expression)))
+            }
+
+            "MAX" | "MIN" => {
+                self.expect("(")?;
+                let left = Box::new(self.parse_expression()?);
+                self.expect(",")?;
+                let right = Box::new(self.parse_expression()?);
+                self.expect(")")?;
+
+                if name == "MAX" {
+                    Ok(Expression::Max(left, right))
+                } else {
+                    Ok(Expression::Min(left, right))
+                }
+            }
+
+            "ADDR" | "SIZEOF" => {
+                self.expect("(")?;
+                let section_name = self.expect_name(Mode::Name)?;
+                self.expect(")")?;
+
+                if name == "ADDR" {
+                    Ok(Expression::Address(section_name))
+                } else {
+                    Ok(Expression::SizeOf(section_name))
+                }
+            }
+
+            "DEFINED" => {
+                self.expect("(")?;
+                let symbol = self.expect_name(Mode::Expression)?;
+                self.expect(")")?;
+                Ok(Expression::Defined(symbol))
+            }
+
+            _ => Ok(Expression::Symbol(name)),
+        }
+    }
+
+    // helpers
+
+    fn accept(&mut self, mode: Mode, operator: &str) -> Result<bool, ParseError> {
+        if matches!(self.lexer.peek(mode)?, Token::Operator(o) if o == operator) {
+            self.lexer.next(mode)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn accept_name(&mut self, keyword: &str) -> Result<bool, ParseError> {
+        if matches!(self.lexer.peek(Mode::Expression)?, Token::Name(ref name) if name == keyword) {
+            self.lexer.next(Mode::Expression)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), ParseError> {
+        match self.lexer.next(Mode::Expression)? {
+            Token::Operator(o) if o == operator => Ok(()),
+            token => Err(self.lexer.error(format!("expected `{}`, found {}", operator, Self::describe(&token)))),
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if self.accept_name(keyword)? {
+            Ok(())
+        } else {
+            let token = self.lexer.peek(Mode::Expression)?;
+            Err(self.lexer.error(format!("expected `{}`, found {}", keyword, Self::describe(&token))))
+        }
+    }
+
+    fn expect_name(&mut self, mode: Mode) -> Result<String, ParseError> {
+        match self.lexer.next(mode)? {
+            Token::Name(name) | Token::String(name) => Ok(name),
+            token => Err(self.unexpected(token)),
+        }
+    }
+
+    fn unexpected(&self, token: Token) -> ParseError {
+        self.lexer.error(format!("unexpected {}", Self::describe(&token)))
+    }
+
+    fn describe(token: &Token) -> String {
+        match token {
+            Token::Name(name) => format!("`{}`", name),
+            Token::Number(value) => format!("number `{}`", value),
+            Token::String(value) => format!("string \"{}\"", value),
+            Token::Operator(operator) => format!("`{}`", operator),
+            Token::End => "end of script".to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Formats an expression with explicit parentheses.
+    fn format_expression(expression: &Expression) -> String {
+        match expression {
+            Expression::Number(value) => value.to_string(),
+            Expression::Symbol(name) => name.to_owned(),
+            Expression::LocationCounter => ".".to_owned(),
+            Expression::Unary(operator, operand) => format!("({:?} {})", operator, format_expression(operand)),
+
+            Expression::Binary(operator, left, right) => {
+                format!("({} {:?} {})", format_expression(left), operator, format_expression(right))
+            }
+
+            Expression::Conditional(condition, left, right) => format!(
+                "({} ? {} : {})",
+                format_expression(condition),
+                format_expression(left),
+                format_expression(right)
+            ),
+
+            Expression::Align(None, alignment) => format!("ALIGN({})", format_expression(alignment)),
+
+            Expression::Align(Some(expression), alignment) => {
+                format!("ALIGN({}, {})", format_expression(expression), format_expression(alignment))
+            }
+
+            expression => format!("{:?}", expression),
+        }
+    }
+
+    fn parse_expression(source: &str) -> String {
+        let script = parse(&format!("x = {};", source)).unwrap();
+
+        match &script.statements[..] {
+            [Statement::Assignment(assignment)] => format_expression(&assignment.expression),
+            statements => panic!("unexpected statements {:?}", statements),
+        }
+    }
+
+    fn get_output_section(script: &Script, index: usize) -> &OutputSectionDescription {
+        match &script.statements[index] {
+            Statement::OutputSection(output_section) => output_section,
+            statement => panic!("unexpected statement {:?}", statement),
+        }
+    }
+
+    fn get_input_section(output_section: &OutputSectionDescription, index: usize) -> &InputSectionDescription {
+        match &output_section.commands[index] {
+            OutputSectionCommand::InputSection(input_section) => input_section,
+            command => panic!("unexpected command {:?}", command),
+        }
+    }
+
+    #[test]
+    fn parses_entry() {
+        assert_eq!(parse("ENTRY(main)").unwrap().entry.as_deref(), Some("main"));
+        assert_eq!(parse("/* empty */").unwrap().entry, None);
+    }
+
+    #[test]
+    fn parses_sections() {
+        let script = parse("
+            SECTIONS {
+                . = SIZEOF_HEADERS;
+                .text : { *(.text .text.*) crt0.o(.init) }
+                .note.GNU-stack : { *(.note.GNU-stack) }
+            }
+        ").unwrap();
+
+        assert_eq!(script.statements.len(), 3);
+        assert!(matches!(&script.statements[0], Statement::Assignment(assignment) if assignment.symbol == LOCATION_COUNTER));
+
+        let text = get_output_section(&script, 1);
+
+        assert_eq!(text.name, ".text");
+        assert!(text.address.is_none());
+        assert_eq!(text.commands.len(), 2);
+
+        let all = get_input_section(text, 0);
+        let init = get_input_section(text, 1);
+
+        assert_eq!((all.id, init.id), (0, 1));
+        assert_eq!(all.file_pattern.0, "*");
+        assert_eq!(all.section_patterns.iter().map(|pattern| pattern.0.as_str()).collect::<Vec<_>>(), [".text", ".text.*"]);
+        assert_eq!(init.file_pattern.0, "crt0.o");
+
+        // names may contain `-` outside expressions
+        assert_eq!(get_output_section(&script, 2).name, ".note.GNU-stack");
+    }
+
+    #[test]
+    fn parses_output_section_address_alignment_noload_and_fill() {
+        let script = parse("
+            SECTIONS {
+                .text 0x1000 : ALIGN(16) { *(.text) } =0x90909090
+                .bss (NOLOAD) : { *(.bss) }
+                .data ADDR(.text) + SIZEOF(.text) (NOLOAD) : { *(.data) }
+            }
+        ").unwrap();
+
+        let text = get_output_section(&script, 0);
+
+        assert_eq!(text.address.as_ref().map(format_expression).as_deref(), Some("4096"));
+        assert_eq!(text.alignment.as_ref().map(format_expression).as_deref(), Some("16"));
+        assert_eq!(text.fill.as_ref().map(format_expression).as_deref(), Some("2425393296"));
+        assert!(!text.noload);
+
+        let bss = get_output_section(&script, 1);
+
+        assert!(bss.noload);
+        assert!(bss.address.is_none());
+        assert!(bss.fill.is_none());
+
+        let data = get_output_section(&script, 2);
+
+        assert!(data.noload);
+        assert_eq!(
+            data.address.as_ref().map(format_expression).as_deref(),
+            Some("(Address(\".text\") Add SizeOf(\".text\"))")
+        );
+    }
+
+    #[test]
+    fn parses_keep_and_input_section_flags() {
+        let script = parse("
+            SECTIONS {
+                .init : {
+                    KEEP(*(.init))
+                    INPUT_SECTION_FLAGS(SHF_ALLOC & !SHF_WRITE) *(.*)
+                }
+            }
+        ").unwrap();
+
+        let init = get_output_section(&script, 0);
+        let kept = get_input_section(init, 0);
+        let flagged = get_input_section(init, 1);
+
+        assert!(kept.keep);
+        assert!(!flagged.keep);
+        assert_eq!(flagged.flags.required, elf::SHF_ALLOC as u64);
+        assert_eq!(flagged.flags.forbidden, elf::SHF_WRITE as u64);
+
+        assert!(parse("SECTIONS { .x : { INPUT_SECTION_FLAGS(SHF_BOGUS) *(.x) } }").is_err());
+    }
+
+    #[test]
+    fn parses_provide_and_compound_assignments() {
+        let script = parse("
+            PROVIDE(__heap = .);
+            SECTIONS {
+                .data : { PROVIDE_HIDDEN(__data_start = .); . += 0x10; *(.data) }
+                __end <<= 2;
+            }
+        ").unwrap();
+
+        let Statement::Assignment(heap) = &script.statements[0] else {
+            panic!("expected an assignment");
+        };
+
+        assert!(heap.provide);
+        assert_eq!(heap.symbol, "__heap");
+
+        let data = get_output_section(&script, 1);
+
+        let OutputSectionCommand::Assignment(data_start) = &data.commands[0] else {
+            panic!("expected an assignment");
+        };
+
+        let OutputSectionCommand::Assignment(advance) = &data.commands[1] else {
+            panic!("expected an assignment");
+        };
+
+        assert!(data_start.provide);
+        assert_eq!(advance.operator, AssignmentOperator::Add);
+
+        let Statement::Assignment(end) = &script.statements[2] else {
+            panic!("expected an assignment");
+        };
+
+        assert_eq!(end.operator, AssignmentOperator::ShiftLeft);
+        assert!(!end.provide);
+
+        assert!(parse("PROVIDE(. = 0x100);").is_err());
+    }
+
+    #[test]
+    fn parses_assert() {
+        let script = parse("
+            ASSERT(. < 0x10000, \"image is too large\")
+            SECTIONS { .text : { ASSERT(DEFINED(_start), no_entry); *(.text) } }
+        ").unwrap();
+
+        let Statement::Assert(assert) = &script.statements[0] else {
+            panic!("expected an assert");
+        };
+
+        assert_eq!(assert.message, "image is too large");
+        assert_eq!(format_expression(&assert.expression), "(. Less 65536)");
+
+        let OutputSectionCommand::Assert(assert) = &get_output_section(&script, 1).commands[0] else {
+            panic!("expected an assert");
+        };
+
+        assert_eq!(assert.message, "no_entry");
+        assert_eq!(format_expression(&assert.expression), "Defined(\"_start\")");
+    }
+
+    #[test]
+    fn parses_align() {
+        assert_eq!(parse_expression("ALIGN(4)"), "ALIGN(4)");
+        assert_eq!(parse_expression("ALIGN(. + 1, 8)"), "ALIGN((. Add 1), 8)");
+    }
+
+    #[test]
+    fn expression_precedence() {
+        assert_eq!(parse_expression("1 + 2 * 3"), "(1 Add (2 Multiply 3))");
+        assert_eq!(parse_expression("(1 + 2) * 3"), "((1 Add 2) Multiply 3)");
+        assert_eq!(parse_expression("1 - 2 - 3"), "((1 Subtract 2) Subtract 3)");
+        assert_eq!(parse_expression("1 << 2 + 3"), "(1 ShiftLeft (2 Add 3))");
+        assert_eq!(parse_expression("a < b == c"), "((a Less b) Equal c)");
+        assert_eq!(parse_expression("a & b | c"), "((a BitwiseAnd b) BitwiseOr c)");
+        assert_eq!(parse_expression("a || b && c"), "(a Or (b And c))");
+        assert_eq!(parse_expression("-a * ~b"), "((Negate a) Multiply (BitwiseNot b))");
+        assert_eq!(parse_expression("!a + +b"), "((Not a) Add b)");
+        assert_eq!(parse_expression("a ? b : c ? d : e"), "(a ? b : (c ? d : e))");
+        assert_eq!(parse_expression("a >= b ? 1 : 2"), "((a GreaterOrEqual b) ? 1 : 2)");
+    }
+
+    #[test]
+    fn reports_error_lines() {
+        let error = parse("ENTRY(_start)\nSECTIONS {\n  .text : { *(.text) \n").unwrap_err();
+
+        assert_eq!(error.line, 4);
+        assert!(error.message.contains("end of script"), "{}", error.message);
+
+        let error = parse("x = 1\ny = 2;").unwrap_err();
+
+        assert_eq!(error.line, 2);
+        assert_eq!(error.message, "expected `;`, found `y`");
+    }
+}
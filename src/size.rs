@@ -0,0 +1,561 @@
+use std::{collections::HashMap, fmt::Write, path::Path};
+use crate::report::{InputSectionReport, LinkReport, SymbolReport};
+
+/// Grouping of output bytes set by `--print-size-report[=crate|archive]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeGrouping {
+    /// Crate of the demangled Rust path, the input file for other symbols.
+    Crate,
+    /// Input archive, or object file outside archives.
+    Archive,
+}
+
+/// Bytes of a group, counted separately for file and BSS sections.
+#[derive(Debug, Default, Clone, Copy)]
+struct Size {
+    file: usize,
+    bss: usize,
+}
+
+impl Size {
+    fn add(&mut self, size: usize, nobits: bool) {
+        if nobits {
+            self.bss += size;
+        } else {
+            self.file += size;
+        }
+    }
+
+    fn total(&self) -> usize {
+        self.file + self.bss
+    }
+}
+
+/// A range of an input section attributed to a symbol, bytes outside
+/// symbols are attributed to the section.
+pub struct SizeItem<'a> {
+    /// Demangled symbol name or `<section>` for unnamed bytes.
+    pub name: String,
+    pub group: String,
+    pub output_section: &'a str,
+    pub size: usize,
+    /// Occupies memory only, has no contents in the file.
+    pub nobits: bool,
+}
+
+/// Splits placed input sections into symbol ranges of `st_size` bytes,
+/// a range ends at the next symbol at the latest. Symbols of unknown size
+/// extend to the next symbol. Aliases at the same address share one
+/// range, a global name is preferred.
+pub fn get_size_items(report: &LinkReport, grouping: SizeGrouping) -> Vec<SizeItem<'_>> {
+    let mut items = Vec::new();
+
+    for output_section in report.output_sections.iter() {
+        for section in output_section.input_sections.iter() {
+            let address = section.address.unwrap_or_default();
+            let end_address = address + section.size;
+
+            // (address, symbol, the largest size of the aliases)
+            let mut symbols = Vec::<(usize, &SymbolReport, usize)>::new();
+
+            for symbol in section.symbols.iter() {
+                match symbols.last_mut() {
+                    Some((last_address, last_symbol, last_size)) if *last_address == symbol.address => {
+                        if last_symbol.local && !symbol.local {
+                            *last_symbol = symbol;
+                        }
+
+                        *last_size = (*last_size).max(symbol.size);
+                    }
+
+                    _ => symbols.push((symbol.address, symbol, symbol.size)),
+                }
+            }
+
+            let section_group = get_file_group(section, grouping);
+
+            let mut push_item = |name: String, group: String, size: usize| {
+                if size != 0 {
+                    items.push(SizeItem {
+                        name,
+                        group,
+                        output_section: &output_section.name,
+                        size,
+                        nobits: output_section.nobits,
+                    });
+                }
+            };
+
+            let mut symbols_size = 0;
+
+            for (index, (symbol_address, symbol, symbol_size)) in symbols.iter().enumerate() {
+                let next_address = symbols
+                    .get(index + 1)
+                    .map_or(end_address, |(address, _, _)| *address);
+
+                let extent = next_address.saturating_sub(*symbol_address);
+                let size = if *symbol_size == 0 { extent } else { extent.min(*symbol_size) };
+
+                let group = match grouping {
+                    SizeGrouping::Crate => get_crate_name(&symbol.demangled).unwrap_or(&section_group),
+                    SizeGrouping::Archive => &section_group,
+                };
+
+                push_item(symbol.demangled.to_owned(), group.to_owned(), size);
+                symbols_size += size;
+            }
+
+            push_item(format!("<{}>", section.name), section_group, section.size.saturating_sub(symbols_size));
+        }
+    }
+
+    items
+}
+
+/// Formats output bytes by groups and then by symbols, largest first,
+/// followed by alignment padding and BSS versus file bytes.
+pub fn generate_size_report(report: &LinkReport, grouping: SizeGrouping) -> String {
+    let mut text = String::new();
+
+    let mut output_sections = report.output_sections.iter().collect::<Vec<_>>();
+    output_sections.sort_by_key(|output_section| output_section.address);
+
+    // totals
+
+    let mut total = Size::default();
+    let mut section_padding = 0;
+    let mut gap_padding = 0;
+    let mut previous_end = None;
+
+    writeln!(&mut text, "Output sections\n").expect("cannot append string");
+    writeln!(&mut text, "{:<24}{:>12}{:>12}{:>12}", "section", "size", "padding", "kind").expect("cannot append string");
+
+    for output_section in output_sections.iter() {
+        let input_size = output_section.input_sections
+            .iter()
+            .map(|section| section.size)
+            .sum::<usize>();
+
+        let padding = output_section.size.saturating_sub(input_size);
+        let kind = if output_section.nobits { "bss" } else { "file" };
+
+        writeln!(&mut text, "{:<24}{:>12}{:>12}{:>12}", output_section.name, output_section.size, padding, kind).expect("cannot append string");
+
+        total.add(output_section.size, output_section.nobits);
+        section_padding += padding;
+
+        if let Some(previous_end) = previous_end {
+            gap_padding += output_section.address.saturating_sub(previous_end);
+        }
+
+        previous_end = Some(output_section.address + output_section.size);
+    }
+
+    writeln!(&mut text).expect("cannot append string");
+    writeln!(&mut text, "file bytes:                  {:>12}", total.file).expect("cannot append string");
+    writeln!(&mut text, "bss bytes:                   {:>12}", total.bss).expect("cannot append string");
+    writeln!(&mut text, "padding in output sections:  {:>12}", section_padding).expect("cannot append string");
+    writeln!(&mut text, "padding between sections:    {:>12}", gap_padding).expect("cannot append string");
+
+    // groups
+
+    let items = get_size_items(report, grouping);
+
+    let mut groups = HashMap::<&str, (Size, HashMap<&str, Size>)>::new();
+
+    for item in items.iter() {
+        let (group_size, symbols) = groups.entry(item.group.as_str()).or_default();
+
+        group_size.add(item.size, item.nobits);
+        symbols.entry(item.name.as_str()).or_default().add(item.size, item.nobits);
+    }
+
+    let mut groups = groups.into_iter().collect::<Vec<_>>();
+    groups.sort_by(|(a_name, (a_size, _)), (b_name, (b_size, _))| b_size.total().cmp(&a_size.total()).then(a_name.cmp(b_name)));
+
+    let title = match grouping {
+        SizeGrouping::Crate => "crate",
+        SizeGrouping::Archive => "archive",
+    };
+
+    writeln!(&mut text, "\nBy {}\n", title).expect("cannot append string");
+    writeln!(&mut text, "{:>12}{:>12}{:>8}  name", "file", "bss", "%").expect("cannot append string");
+
+    for (group_name, (group_size, symbols)) in groups {
+        writeln!(&mut text).expect("cannot append string");
+        write_size_line(&mut text, group_size, total.total(), group_name, 0);
+
+        let mut symbols = symbols.into_iter().collect::<Vec<_>>();
+        symbols.sort_by(|(a_name, a_size), (b_name, b_size)| b_size.total().cmp(&a_size.total()).then(a_name.cmp(b_name)));
+
+        for (symbol_name, symbol_size) in symbols {
+            write_size_line(&mut text, symbol_size, total.total(), symbol_name, 4);
+        }
+    }
+
+    text
+}
+
+fn write_size_line(text: &mut String, size: Size, total: usize, name: &str, indent: usize) {
+    let percent = if total == 0 { 0.0 } else { size.total() as f64 * 100.0 / total as f64 };
+
+    writeln!(text, "{:>12}{:>12}{:>7.2}%  {:indent$}{}", size.file, size.bss, percent, "", name, indent = indent).expect("cannot append string");
+}
+
+/// Returns the crate of a demangled Rust path, the first path segment.
+/// Trait implementations `<Type as Trait>::method` take the type crate.
+pub fn get_crate_name(demangled: &str) -> Option<&str> {
+    let path = demangled.trim_start_matches(['<', '&', '*']);
+    let path = path.strip_prefix("mut ").unwrap_or(path);
+    let (crate_name, _) = path.split_once("::")?;
+
+    let is_identifier = !crate_name.is_empty()
+        && crate_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    is_identifier.then_some(crate_name)
+}
+
+/// Returns the archive path, or the crate name of the archive for crate
+/// grouping (`libNAME-HASH.rlib` is `NAME`). Returns the object file name
+/// outside archives.
+fn get_file_group(section: &InputSectionReport, grouping: SizeGrouping) -> String {
+    match section.archive {
+        Some(ref archive) if grouping == SizeGrouping::Archive => archive.to_owned(),
+
+        Some(ref archive) => {
+            let stem = Path::new(archive)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| archive.to_owned());
+
+            let name = stem.strip_prefix("lib").unwrap_or(&stem);
+            let name = name.split_once('-').map_or(name, |(name, _)| name);
+
+            name.to_owned()
+        }
+
+        None => section.file.to_owned(),
+    }
+}
+
+/// Formats size changes between two link reports: totals per output
+/// section, then added, removed, grown and shrunk crates and symbols,
+/// largest changes first.
+pub fn generate_size_diff(old: &LinkReport, new: &LinkReport) -> String {
+    let mut text = String::new();
+
+    // output sections
+
+    let mut sections = Vec::<(&str, usize, usize)>::new();
+
+    for output_section in old.output_sections.iter() {
+        sections.push((&output_section.name, output_section.size, 0));
+    }
+
+    for output_section in new.output_sections.iter() {
+        match sections.iter_mut().find(|(name, _, _)| *name == output_section.name) {
+            Some((_, _, new_size)) => *new_size = output_section.size,
+            None => sections.push((&output_section.name, 0, output_section.size)),
+        }
+    }
+
+    writeln!(&mut text, "Output sections\n").expect("cannot append string");
+    writeln!(&mut text, "{:<24}{:>12}{:>12}{:>12}", "section", "old", "new", "delta").expect("cannot append string");
+
+    let (mut old_total, mut new_total) = (0, 0);
+
+    for (name, old_size, new_size) in sections {
+        write_diff_line(&mut text, name, old_size, new_size);
+
+        old_total += old_size;
+        new_total += new_size;
+    }
+
+    write_diff_line(&mut text, "total", old_total, new_total);
+
+    // crates and symbols
+
+    let old_items = get_size_items(old, SizeGrouping::Crate);
+    let new_items = get_size_items(new, SizeGrouping::Crate);
+
+    let group_sizes = |items: &[SizeItem]| {
+        let mut sizes = HashMap::<String, usize>::new();
+
+        for item in items {
+            *sizes.entry(item.group.to_owned()).or_default() += item.size;
+        }
+
+        sizes
+    };
+
+    let symbol_sizes = |items: &[SizeItem]| {
+        let mut sizes = HashMap::<String, usize>::new();
+
+        for item in items {
+            *sizes.entry(item.name.to_owned()).or_default() += item.size;
+        }
+
+        sizes
+    };
+
+    write_changes(&mut text, "Crates", group_sizes(&old_items), group_sizes(&new_items));
+    write_changes(&mut text, "Symbols", symbol_sizes(&old_items), symbol_sizes(&new_items));
+
+    text
+}
+
+fn write_diff_line(text: &mut String, name: &str, old_size: usize, new_size: usize) {
+    let delta = new_size as i64 - old_size as i64;
+
+    writeln!(text, "{:<24}{:>12}{:>12}{:>+12}", name, old_size, new_size, delta).expect("cannot append string");
+}
+
+/// Writes changed entries grouped by the kind of the change.
+fn write_changes(text: &mut String, title: &str, old: HashMap<String, usize>, mut new: HashMap<String, usize>) {
+    let mut changes = Vec::<(&str, String, usize, usize)>::new();
+
+    for (name, old_size) in old {
+        let new_size = new.remove(&name).unwrap_or_default();
+
+        let kind = match new_size {
+            0 => "removed",
+            _ if new_size > old_size => "grown",
+            _ if new_size < old_size => "shrunk",
+            _ => continue,
+        };
+
+        changes.push((kind, name, old_size, new_size));
+    }
+
+    for (name, new_size) in new {
+        changes.push(("added", name, 0, new_size));
+    }
+
+    writeln!(text, "\n{}", title).expect("cannot append string");
+
+    if changes.is_empty() {
+        writeln!(text, "\nno changes").expect("cannot append string");
+        return;
+    }
+
+    for kind in ["added", "removed", "grown", "shrunk"] {
+        let mut kind_changes = changes
+            .iter()
+            .filter(|(change_kind, _, _, _)| *change_kind == kind)
+            .collect::<Vec<_>>();
+
+        if kind_changes.is_empty() {
+            continue;
+        }
+
+        kind_changes.sort_by(|(_, a_name, a_old, a_new), (_, b_name, b_old, b_new)| {
+            b_new.abs_diff(*b_old).cmp(&a_new.abs_diff(*a_old)).then(a_name.cmp(b_name))
+        });
+
+        writeln!(text, "\n{} ({}):", kind, kind_changes.len()).expect("cannot append string");
+        writeln!(text, "{:>12}{:>12}{:>12}  name", "old", "new", "delta").expect("cannot append string");
+
+        for (_, name, old_size, new_size) in kind_changes {
+            let delta = *new_size as i64 - *old_size as i64;
+            writeln!(text, "{:>12}{:>12}{:>+12}  {}", old_size, new_size, delta, name).expect("cannot append string");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::report::{OutputSectionReport, SectionReason};
+    use super::*;
+
+    fn symbol(name: &str, address: usize, size: usize, local: bool) -> SymbolReport {
+        SymbolReport {
+            name: name.to_owned(),
+            demangled: name.to_owned(),
+            address,
+            size,
+            local,
+        }
+    }
+
+    fn section(name: &str, archive: Option<&str>, address: usize, size: usize, symbols: Vec<SymbolReport>) -> InputSectionReport {
+        InputSectionReport {
+            name: name.to_owned(),
+            file: "file.o".to_owned(),
+            archive: archive.map(str::to_owned),
+            address: Some(address),
+            size,
+            alignment: 1,
+            relocations: 0,
+            reason: SectionReason::Kept,
+            symbols,
+        }
+    }
+
+    fn report(input_sections: Vec<InputSectionReport>) -> LinkReport {
+        let size = input_sections.iter().map(|section| section.size).sum();
+
+        LinkReport {
+            output: "out".to_owned(),
+            entry: "_start".to_owned(),
+            entry_address: 0,
+            end_address: size,
+            output_sections: vec![OutputSectionReport {
+                name: ".text".to_owned(),
+                address: 0,
+                size,
+                alignment: 1,
+                nobits: false,
+                input_sections,
+            }],
+            discarded_sections: Vec::new(),
+            linker_symbols: Vec::new(),
+        }
+    }
+
+    fn items(report: &LinkReport, grouping: SizeGrouping) -> Vec<(String, String, usize)> {
+        get_size_items(report, grouping)
+            .into_iter()
+            .map(|item| (item.name, item.group, item.size))
+            .collect()
+    }
+
+    fn item(name: &str, group: &str, size: usize) -> (String, String, usize) {
+        (name.to_owned(), group.to_owned(), size)
+    }
+
+    #[test]
+    fn crate_name_is_the_first_path_segment() {
+        assert_eq!(get_crate_name("core::fmt::write"), Some("core"));
+        assert_eq!(get_crate_name("my_crate::Foo::new"), Some("my_crate"));
+        assert_eq!(get_crate_name("<alloc::vec::Vec<T> as core::ops::drop::Drop>::drop"), Some("alloc"));
+        assert_eq!(get_crate_name("<&mut std::io::Stdout as core::fmt::Write>::write_str"), Some("std"));
+        assert_eq!(get_crate_name("<*const u8 as core::fmt::Debug>::fmt"), None);
+    }
+
+    #[test]
+    fn non_rust_names_have_no_crate() {
+        assert_eq!(get_crate_name("memcpy"), None);
+        assert_eq!(get_crate_name("_start"), None);
+        assert_eq!(get_crate_name("::leading"), None);
+        assert_eq!(get_crate_name("operator new(unsigned int)::x"), None);
+    }
+
+    #[test]
+    fn symbol_sizes_split_sections_and_the_rest_is_unnamed() {
+        let report = report(vec![section(".text.f", None, 0, 16, vec![
+            symbol("f", 0, 6, false),
+            // aliases share the range, the global name is preferred
+            symbol("g.local", 8, 2, true),
+            symbol("g", 8, 4, false),
+        ])]);
+
+        assert_eq!(items(&report, SizeGrouping::Archive), [
+            item("f", "file.o", 6),
+            item("g", "file.o", 4),
+            item("<.text.f>", "file.o", 6),
+        ]);
+    }
+
+    #[test]
+    fn symbols_of_unknown_size_extend_to_the_next_symbol() {
+        let report = report(vec![section(".text", None, 0, 10, vec![
+            symbol("a", 2, 0, false),
+            symbol("b", 6, 0, false),
+        ])]);
+
+        assert_eq!(items(&report, SizeGrouping::Archive), [
+            item("a", "file.o", 4),
+            item("b", "file.o", 4),
+            item("<.text>", "file.o", 2),
+        ]);
+    }
+
+    #[test]
+    fn crate_grouping_uses_symbol_paths_and_archive_names() {
+        let report = report(vec![section(".text", Some("deps/libfoo-0123abcd.rlib"), 0, 8, vec![
+            symbol("bar::f", 0, 4, false),
+            symbol("memcpy", 4, 4, false),
+        ])]);
+
+        assert_eq!(items(&report, SizeGrouping::Crate), [
+            item("bar::f", "bar", 4),
+            item("memcpy", "foo", 4),
+        ]);
+
+        assert_eq!(items(&report, SizeGrouping::Archive), [
+            item("bar::f", "deps/libfoo-0123abcd.rlib", 4),
+            item("memcpy", "deps/libfoo-0123abcd.rlib", 4),
+        ]);
+    }
+
+    fn sizes(entries: &[(&str, usize)]) -> HashMap<String, usize> {
+        entries
+            .iter()
+            .map(|&(name, size)| (name.to_owned(), size))
+            .collect()
+    }
+
+    #[test]
+    fn changes_are_classified_and_sorted_by_delta() {
+        let mut text = String::new();
+
+        write_changes(
+            &mut text,
+            "Symbols",
+            sizes(&[("same", 4), ("grown", 4), ("grown_more", 2), ("shrunk", 9), ("removed", 3)]),
+            sizes(&[("same", 4), ("grown", 6), ("grown_more", 12), ("shrunk", 1), ("added", 5)])
+        );
+
+        assert_eq!(text, concat!(
+            "\nSymbols\n",
+            "\nadded (1):\n",
+            "         old         new       delta  name\n",
+            "           0           5          +5  added\n",
+            "\nremoved (1):\n",
+            "         old         new       delta  name\n",
+            "           3           0          -3  removed\n",
+            "\ngrown (2):\n",
+            "         old         new       delta  name\n",
+            "           2          12         +10  grown_more\n",
+            "           4           6          +2  grown\n",
+            "\nshrunk (1):\n",
+            "         old         new       delta  name\n",
+            "           9           1          -8  shrunk\n",
+        ));
+    }
+
+    #[test]
+    fn unchanged_sizes_are_reported_as_no_changes() {
+        let mut text = String::new();
+
+        write_changes(&mut text, "Crates", sizes(&[("core", 8)]), sizes(&[("core", 8)]));
+
+        assert_eq!(text, "\nCrates\n\nno changes\n");
+    }
+
+    #[test]
+    fn size_diff_compares_sections_crates_and_symbols() {
+        let old = report(vec![section(".text", None, 0, 8, vec![
+            symbol("foo::a", 0, 4, false),
+            symbol("foo::b", 4, 4, false),
+        ])]);
+
+        let new = report(vec![section(".text", None, 0, 10, vec![
+            symbol("foo::a", 0, 4, false),
+            symbol("bar::c", 4, 6, false),
+        ])]);
+
+        let diff = generate_size_diff(&old, &new);
+
+        assert!(diff.starts_with(concat!(
+            "Output sections\n\n",
+            "section                          old         new       delta\n",
+            ".text                              8          10          +2\n",
+            "total                              8          10          +2\n",
+        )));
+
+        assert!(diff.contains("\nCrates\n\nadded (1):\n         old         new       delta  name\n           0           6          +6  bar\n"));
+        assert!(diff.contains("\nshrunk (1):\n         old         new       delta  name\n           8           4          -4  foo\n"));
+        assert!(diff.contains("\nremoved (1):\n         old         new       delta  name\n           4           0          -4  foo::b\n"));
+    }
+}
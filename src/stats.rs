@@ -0,0 +1,191 @@
+use std::{collections::HashSet, fmt::Write, time::{Duration, Instant}};
+use crate::elf::{context::{Context, SymbolEntry}, utils};
+use crate::layout::Layout;
+
+/// Link phase measured by `Timer`.
+pub struct Phase {
+    pub name: &'static str,
+    /// Offset from the start of the link.
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+/// Measures consecutive link phases, each phase lasts from the end of the
+/// previous one.
+pub struct Timer {
+    start: Instant,
+    last: Instant,
+    pub phases: Vec<Phase>,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        let now = Instant::now();
+
+        Self {
+            start: now,
+            last: now,
+            phases: Vec::new(),
+        }
+    }
+
+    /// Records the phase that ends now.
+    pub fn finish(&mut self, name: &'static str) {
+        let now = Instant::now();
+
+        self.phases.push(Phase {
+            name,
+            start: self.last - self.start,
+            duration: now - self.last,
+        });
+
+        self.last = now;
+    }
+
+    /// Formats phases in the Chrome trace event format, loadable by
+    /// `chrome://tracing` and Perfetto.
+    pub fn to_chrome_trace(&self) -> String {
+        let events = self.phases
+            .iter()
+            .map(|phase| serde_json::json!({
+                "name": phase.name,
+                "cat": "link",
+                "ph": "X",
+                "ts": phase.start.as_micros() as u64,
+                "dur": phase.duration.as_micros() as u64,
+                "pid": std::process::id(),
+                "tid": 0,
+            }))
+            .chain(std::iter::once(serde_json::json!({
+                "name": "link",
+                "cat": "link",
+                "ph": "X",
+                "ts": 0,
+                "dur": (self.last - self.start).as_micros() as u64,
+                "pid": std::process::id(),
+                "tid": 0,
+            })))
+            .collect::<Vec<_>>();
+
+        let trace = serde_json::json!({
+            "traceEvents": events,
+            "displayTimeUnit": "ms",
+        });
+
+        serde_json::to_string_pretty(&trace).expect("cannot serialize time trace")
+    }
+}
+
+/// Formats phase durations, followed by counts of inputs, sections,
+/// symbols and relocations, and the output size.
+pub fn generate_stats(
+    timer: &Timer,
+    context: &Context,
+    layout: &Layout,
+    live_sections: usize,
+    output_size: u64
+) -> String {
+    let mut text = String::new();
+
+    writeln!(&mut text, "Phases\n").expect("cannot append string");
+
+    let total = timer.last - timer.start;
+
+    for phase in timer.phases.iter() {
+        write_duration(&mut text, phase.name, phase.duration, total);
+    }
+
+    write_duration(&mut text, "total", total, total);
+
+    // inputs
+
+    let archives = context.objects
+        .iter()
+        .filter(|object| object.is_archive_member())
+        .map(|object| object.get_input_path())
+        .collect::<HashSet<_>>();
+
+    let members = context.objects
+        .iter()
+        .filter(|object| object.is_archive_member())
+        .count();
+
+    // sections and relocations
+
+    let mut input_sections = 0;
+    let mut relocations = 0;
+    let mut placed_relocations = 0;
+
+    for object in context.objects.iter() {
+        for section in object.sections() {
+            let section = (*section).borrow();
+
+            if section.metadata {
+                continue;
+            }
+
+            input_sections += 1;
+            relocations += section.relocations.len();
+
+            if layout.section_addresses.contains_key(&section.uid) {
+                placed_relocations += section.relocations.len();
+            }
+        }
+    }
+
+    // symbols
+
+    let mut global_symbols = 0;
+    let mut local_symbols = 0;
+
+    for (symbol_name, symbol_entry) in context.symbol_map.iter() {
+        match symbol_entry {
+            SymbolEntry::Resolved(_) if symbol_name.contains(utils::LOCAL_SYMBOL_SUFFIX) => local_symbols += 1,
+            SymbolEntry::Resolved(_) => global_symbols += 1,
+            SymbolEntry::Unresolved { .. } => {}
+        }
+    }
+
+    let bss_bytes = layout.output_sections
+        .iter()
+        .filter(|output_section| output_section.nobits)
+        .map(|output_section| output_section.size)
+        .sum::<usize>();
+
+    writeln!(&mut text, "\nStatistics\n").expect("cannot append string");
+
+    let counts = [
+        ("object files", context.objects.len() - members),
+        ("archives", archives.len()),
+        ("archive members", members),
+        ("input sections", input_sections),
+        ("live sections", live_sections),
+        ("placed sections", layout.section_addresses.len()),
+        ("output sections", layout.output_sections.len()),
+        ("global symbols", global_symbols),
+        ("local symbols", local_symbols),
+        ("linker symbols", layout.symbols.len()),
+        ("relocations", relocations),
+        ("applied relocations", placed_relocations),
+        ("bss bytes", bss_bytes),
+        ("output bytes", output_size as usize),
+    ];
+
+    for (name, count) in counts {
+        writeln!(&mut text, "{:<24}{:>12}", name, count).expect("cannot append string");
+    }
+
+    text
+}
+
+fn write_duration(text: &mut String, name: &str, duration: Duration, total: Duration) {
+    let percent = if total.is_zero() { 0.0 } else { duration.as_secs_f64() * 100.0 / total.as_secs_f64() };
+
+    writeln!(text, "{:<24}{:>10.3} ms{:>8.2}%", name, duration.as_secs_f64() * 1000.0, percent).expect("cannot append string");
+}
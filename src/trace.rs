@@ -0,0 +1,72 @@
+use std::{collections::BTreeSet, fmt::Write};
+use crate::archive::Extraction;
+use crate::elf::{context::{Context, SymbolEntry}, file::ElfSymbolType, utils};
+
+/// Formats every global entry of the symbol in input files like ld `-y`:
+/// references and strong, weak or common definitions in input order,
+/// followed by the definition picked by the resolution. The symbol is a
+/// mangled or demangled name.
+pub fn trace_symbol(context: &Context, symbol_name: &str) -> String {
+    let mut text = String::new();
+    let mut names = BTreeSet::<String>::new();
+
+    for object in context.objects.iter() {
+        for symbol in object.symbols() {
+            let symbol = (*symbol).borrow();
+
+            let Some(name) = symbol.name.as_deref() else {
+                continue;
+            };
+
+            if name.contains(utils::LOCAL_SYMBOL_SUFFIX) || (name != symbol_name && utils::demangle(name) != symbol_name) {
+                continue;
+            }
+
+            let kind = match symbol.sym_type {
+                ElfSymbolType::External if symbol.weak => "weak reference to",
+                ElfSymbolType::External => "reference to",
+                ElfSymbolType::Internal if symbol.common => "common definition of",
+                ElfSymbolType::Internal if symbol.weak => "weak definition of",
+                ElfSymbolType::Internal => "definition of",
+            };
+
+            writeln!(&mut text, "{}: {} {}", object.get_display_name(), kind, name).expect("cannot append string");
+            names.insert(name.to_owned());
+        }
+    }
+
+    if names.is_empty() {
+        writeln!(&mut text, "{}: no input file defines or references the symbol", symbol_name).expect("cannot append string");
+    }
+
+    for name in names {
+        match context.symbol_map.get(&name) {
+            Some(SymbolEntry::Resolved(weak_symbol)) => {
+                let symbol = weak_symbol.upgrade().expect("cannot get symbol");
+                writeln!(&mut text, "{}: resolved to the definition in {}", name, (*symbol).borrow().get_file_name()).expect("cannot append string");
+            }
+
+            Some(SymbolEntry::Unresolved { weak: true }) => {
+                writeln!(&mut text, "{}: undefined weak, resolved to 0", name).expect("cannot append string");
+            }
+
+            _ => writeln!(&mut text, "{}: undefined", name).expect("cannot append string"),
+        }
+    }
+
+    text
+}
+
+/// Formats extractions like lld `--why-extract`: tab-separated reference,
+/// extracted member and symbol.
+pub fn generate_why_extract(extractions: &[Extraction]) -> String {
+    let mut text = String::new();
+
+    writeln!(&mut text, "reference\textracted\tsymbol").expect("cannot append string");
+
+    for extraction in extractions {
+        writeln!(&mut text, "{}\t{}\t{}", extraction.reference, extraction.extracted, extraction.symbol).expect("cannot append string");
+    }
+
+    text
+}
@@ -0,0 +1,113 @@
+use std::{cell::RefCell, collections::{BTreeMap, HashSet}, fmt, rc::Rc};
+use crate::elf::{context::{Context, SymbolEntry}, file::{ElfRelocationTarget, ElfSection}, utils};
+use crate::layout::SectionMapping;
+
+/// Relocation referencing an undefined symbol.
+#[derive(Debug)]
+pub struct Reference {
+    pub filename: String,
+    pub section: String,
+    pub offset: usize,
+    /// Nearest symbol defined before the relocation in the same section.
+    pub function: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct UndefinedSymbol {
+    pub name: String,
+    pub references: Vec<Reference>,
+}
+
+/// Collects undefined symbols referenced by relocations of live sections.
+///
+/// Symbols assigned by the linker script and `__start_`/`__stop_` symbols
+/// of existing output sections are defined by the linker, references that
+/// are all `STB_WEAK` are not reported.
+pub fn find_undefined_symbols(
+    context: &Context,
+    mapping: &SectionMapping,
+    script_symbols: &HashSet<&str>,
+    live_sections: &[Rc<RefCell<ElfSection>>],
+) -> Vec<UndefinedSymbol> {
+    let mut undefined_symbols = BTreeMap::<String, Vec<Reference>>::new();
+
+    for section in live_sections {
+        let section = (**section).borrow();
+
+        for relocation in section.relocations.iter() {
+            let ElfRelocationTarget::Symbol(ref weak_symbol) = relocation.target else {
+                continue;
+            };
+
+            let symbol = weak_symbol.upgrade().expect("cannot get strong symbol");
+            let symbol = (*symbol).borrow();
+            let symbol_name = symbol.name.as_ref().expect("cannot get symbol name");
+
+            let is_defined = match context.symbol_map.get(symbol_name) {
+                Some(SymbolEntry::Resolved(_)) => true,
+                None => false,
+
+                // undefined weak references resolve to 0
+                Some(SymbolEntry::Unresolved { weak: true }) => true,
+
+                Some(SymbolEntry::Unresolved { weak: false }) => {
+                    script_symbols.contains(symbol_name.as_str()) ||
+                    utils::get_encapsulated_section_name(symbol_name)
+                        .and_then(|section_name| mapping.get_output_section_inputs(section_name))
+                        .is_some()
+                }
+            };
+
+            if is_defined {
+                continue;
+            }
+
+            undefined_symbols
+                .entry(symbol_name.to_owned())
+                .or_default()
+                .push(Reference {
+                    filename: section.get_file_name(),
+                    section: section.name.to_owned(),
+                    offset: relocation.offset,
+                    function: section
+                        .get_enclosing_symbol(relocation.offset)
+                        .map(|(_, name)| name),
+                });
+        }
+    }
+
+    undefined_symbols
+        .into_iter()
+        .map(|(name, references)| UndefinedSymbol { name, references })
+        .collect()
+}
+
+impl fmt::Display for UndefinedSymbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let demangled_name = utils::demangle(&self.name);
+
+        if demangled_name == self.name {
+            write!(f, "undefined symbol: {}", self.name)?;
+        } else {
+            write!(f, "undefined symbol: {} ({})", demangled_name, self.name)?;
+        }
+
+        for reference in self.references.iter() {
+            let function = reference.function
+                .as_ref()
+                .map(|function| format!(" in function {}", utils::demangle(function)))
+                .unwrap_or_default();
+
+            write!(
+                f,
+                "\n>>> referenced by {}:({}+0x{:X}){}",
+                reference.filename,
+                reference.section,
+                reference.offset,
+                function
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
-use std::ops::Range;
+use std::{ops::Range, path::Path};
+use crate::error::WriteError;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct BufferRegion(usize, usize); // (offset_begin, offset_end)
@@ -39,14 +40,6 @@ impl<'a> Writer<'a> {
         self.buffer.clear();
     }
 
-    pub fn append_padding(&mut self, alignment: usize, filler: Option<u8>) {
-        while self.buffer.len() % alignment > 0 {
-            self.buffer.push(
-                filler.unwrap_or(0)
-            );
-        }
-    }
-
     pub fn insert_buffer(&mut self, operation: Operation, buffer: &[u8]) -> BufferRegion {
         self.insert(operation, buffer)
     }
@@ -67,12 +60,8 @@ impl<'a> Writer<'a> {
     }
 
     /// Writes internal buffer to file.
-    pub fn write(&self) {
-        std::fs::write(
-            self.filename,
-            &self.buffer
-        )
-        .unwrap(); // TODO: better error handling
+    pub fn write(&self) -> Result<(), WriteError> {
+        write_file_atomically(self.filename, &self.buffer)
     }
 
     pub fn offset(&self) -> usize {
@@ -102,3 +91,36 @@ impl<'a> Writer<'a> {
         buffer_region
     }
 }
+
+/// Writes the file through a temporary file in the same directory and a
+/// rename, an interrupted write never leaves a truncated file behind.
+pub fn write_file_atomically(path: &str, data: &[u8]) -> Result<(), WriteError> {
+    let file_path = Path::new(path);
+
+    let result = match file_path.file_name() {
+        Some(file_name) => {
+            // the process id keeps parallel links apart
+            let temporary_path = file_path.with_file_name(format!(
+                ".{}.{}.tmp",
+                file_name.to_string_lossy(),
+                std::process::id()
+            ));
+
+            let result = std::fs::write(&temporary_path, data)
+                .and_then(|_| std::fs::rename(&temporary_path, file_path));
+
+            if result.is_err() {
+                let _ = std::fs::remove_file(&temporary_path);
+            }
+
+            result
+        }
+
+        None => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a file path")),
+    };
+
+    result.map_err(|source| WriteError {
+        path: path.to_owned(),
+        source,
+    })
+}